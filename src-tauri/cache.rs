@@ -0,0 +1,66 @@
+//! The last queues heard from the server, kept on disk so there's something to show straight
+//! after starting up, even when the server can't be reached.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::queue::Queue;
+
+#[derive(Debug)]
+pub(crate) struct QueueCache {
+    path: PathBuf,
+    /// Held while writing, so an older snapshot can't overwrite a newer one
+    writing: Mutex<()>,
+}
+
+impl QueueCache {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            writing: Mutex::default(),
+        }
+    }
+
+    /// The queues from last time, or `None` if nothing has been cached yet
+    pub(crate) fn load(&self) -> Result<Option<BTreeMap<u64, Queue>>> {
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Couldn't read {}", self.path.display()))
+            }
+        };
+
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .with_context(|| format!("Couldn't parse cached queues in {}", self.path.display()))
+    }
+
+    /// Replaces the cached queues with whatever `queues` holds by the time it's this caller's turn
+    pub(crate) async fn store(&self, queues: &RwLock<BTreeMap<u64, Queue>>) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let contents = serde_json::to_vec(&*queues.read().await)?;
+
+        replace_file(&self.path, contents).await
+    }
+}
+
+/// Writes the file next to `path` and moves it over, so a crash can't leave half of it behind
+pub(crate) async fn replace_file(path: &Path, contents: Vec<u8>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Couldn't create {}", dir.display()))?;
+    }
+    let partial = path.with_extension("partial");
+    tokio::fs::write(&partial, contents)
+        .await
+        .with_context(|| format!("Couldn't write {}", partial.display()))?;
+    tokio::fs::rename(&partial, path)
+        .await
+        .with_context(|| format!("Couldn't replace {}", path.display()))
+}
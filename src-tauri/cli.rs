@@ -0,0 +1,268 @@
+//! `rq-cli`: the queue operations of the desktop app, for use from a terminal.
+//!
+//! Connects with the same settings as the app, runs a single command and disconnects again, or
+//! keeps showing every queue with `rq-cli dashboard`.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures_util::future::{BoxFuture, FutureExt};
+use rq::{ClientEvent, CommandError, ConnectionState, QHubClient, Queue, Settings, User};
+use serde_json::json;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+mod dashboard;
+
+/// How long to wait for the connection and the initial queue list before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rq-cli",
+    about = "Join, leave and manage QHub queues from the terminal"
+)]
+struct Cli {
+    /// How to print results
+    #[arg(long, short, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List every queue
+    List,
+    /// Show a queue's members and messages
+    Show { id: u64 },
+    /// Join a queue
+    Join { id: u64 },
+    /// Leave a queue
+    Leave { id: u64 },
+    /// Start a queue, letting its members know it's their turn
+    Start { id: u64 },
+    /// Put a started queue back to open
+    Reset { id: u64 },
+    /// Remind the members of a queue that it's waiting on them
+    Nag { id: u64 },
+    /// Close a queue for good
+    Close { id: u64 },
+    /// Create a new queue, which you join straight away
+    Create {
+        name: String,
+        /// Only let members of this group join
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Send a message to everyone in a queue
+    Message { id: u64, text: String },
+    /// Keep showing every queue full-screen, with keys to join, leave, start, nag and message them
+    Dashboard,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    let cli = Cli::parse();
+    let settings = Settings::load()?;
+
+    let client = QHubClient::new(settings);
+    let events = client.subscribe();
+    client.spawn();
+
+    if let Command::Dashboard = cli.command {
+        let result = dashboard::run(&client, events).await;
+        client.shutdown().await;
+        return result;
+    }
+
+    wait_until_ready(&client, events).await?;
+    let result = run(&client, cli.command, cli.output).await;
+    client.shutdown().await;
+
+    result
+}
+
+/// Waits until the client is connected and has listed the queues, failing on the first
+/// connection error rather than retrying like the app does
+async fn wait_until_ready(
+    client: &QHubClient,
+    mut events: broadcast::Receiver<ClientEvent>,
+) -> Result<()> {
+    let ready = async {
+        loop {
+            match events.recv().await? {
+                ClientEvent::QueuesListed(_) => return Ok(()),
+                ClientEvent::ConnectionStatus(status) => {
+                    if let ConnectionState::Reconnecting { .. } = status.state {
+                        bail!(
+                            "Couldn't connect to {}: {}",
+                            status.server_url.unwrap_or_default(),
+                            status.last_error.unwrap_or_default()
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    };
+
+    let server_url = client.settings().await.server_url;
+    timeout(CONNECT_TIMEOUT, ready)
+        .await
+        .with_context(|| format!("Timed out connecting to {}", server_url))?
+}
+
+async fn run(client: &QHubClient, command: Command, output: Output) -> Result<()> {
+    let queue = |id: u64| async move {
+        client
+            .queues()
+            .await
+            .remove(&id)
+            .ok_or_else(|| anyhow!("There is no queue {}", id))
+    };
+
+    // Invocations are only sent once awaited, so the queue can be looked up first
+    let (id, action, done, invocation): (u64, &str, &str, BoxFuture<Result<(), CommandError>>) =
+        match command {
+            Command::List => {
+                let queues: Vec<Queue> = client.queues().await.into_values().collect();
+                match output {
+                    Output::Table => print_queues(&queues),
+                    Output::Json => println!("{}", serde_json::to_string_pretty(&queues)?),
+                }
+                return Ok(());
+            }
+            Command::Show { id } => {
+                let queue = queue(id).await?;
+                match output {
+                    Output::Table => print_queue(&queue),
+                    Output::Json => println!("{}", serde_json::to_string_pretty(&queue)?),
+                }
+                return Ok(());
+            }
+            Command::Create { name, group } => {
+                client.new_queue(&name, group.as_deref()).await?;
+                match output {
+                    Output::Table => println!("Created {}", name),
+                    Output::Json => println!("{}", json!({ "action": "create", "name": name })),
+                }
+                return Ok(());
+            }
+            Command::Dashboard => unreachable!("The dashboard doesn't run single commands"),
+            Command::Join { id } => (id, "join", "Joined", client.join_queue(id).boxed()),
+            Command::Leave { id } => (id, "leave", "Left", client.leave_queue(id).boxed()),
+            Command::Start { id } => (id, "start", "Started", client.start_queue(id).boxed()),
+            Command::Reset { id } => (id, "reset", "Reset", client.reset_queue(id).boxed()),
+            Command::Nag { id } => (id, "nag", "Nagged", client.nag_queue(id).boxed()),
+            Command::Close { id } => (id, "close", "Closed", client.delete_queue(id).boxed()),
+            Command::Message { id, text } => (
+                id,
+                "message",
+                "Sent a message to",
+                async move { client.message_queue(id, &text).await }.boxed(),
+            ),
+        };
+
+    let name = queue(id).await?.name;
+    invocation.await?;
+
+    match output {
+        Output::Table => println!("{} {}", done, name),
+        Output::Json => println!("{}", json!({ "action": action, "id": id, "name": name })),
+    }
+
+    Ok(())
+}
+
+fn print_queues(queues: &[Queue]) {
+    let rows = queues
+        .iter()
+        .map(|queue| {
+            vec![
+                queue.id.to_string(),
+                queue.name.clone(),
+                format!("{:?}", queue.status),
+                queue.restrict_to_group.clone(),
+                member_names(&queue.members),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    print_table(&["ID", "NAME", "STATUS", "GROUP", "MEMBERS"], &rows);
+}
+
+fn print_queue(queue: &Queue) {
+    print_table(
+        &["", ""],
+        &[
+            vec!["ID".to_owned(), queue.id.to_string()],
+            vec!["Name".to_owned(), queue.name.clone()],
+            vec!["Status".to_owned(), format!("{:?}", queue.status)],
+            vec!["Group".to_owned(), queue.restrict_to_group.clone()],
+            vec!["Members".to_owned(), member_names(&queue.members)],
+        ],
+    );
+
+    if !queue.messages.is_empty() {
+        println!();
+        let rows = queue
+            .messages
+            .iter()
+            .map(|message| vec![message.sender.username.clone(), message.content.clone()])
+            .collect::<Vec<_>>();
+        print_table(&["FROM", "MESSAGE"], &rows);
+    }
+}
+
+fn member_names(members: &[User]) -> String {
+    members
+        .iter()
+        .map(|member| member.username.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints rows in columns padded to the widest cell. Headers that are all empty are left out.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let line = cells
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    if headers.iter().any(|header| !header.is_empty()) {
+        print_row(&mut headers.iter().copied());
+    }
+    for row in rows {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
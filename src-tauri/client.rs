@@ -0,0 +1,545 @@
+//! The QHub client itself: the connection, everything known about the queues, and the hub
+//! methods that can be invoked on them. Nothing in here knows about windows or trays; whatever
+//! shows the queues to the user subscribes to [`ClientEvent`]s instead.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{broadcast, Notify, RwLock};
+use tokio::task::JoinHandle;
+use tracing::log::{debug, warn};
+
+use crate::cache::QueueCache;
+use crate::connection::{self, ConnectionInfo, ConnectionStatus, Session};
+use crate::diff::{diff_queue, diff_queues, QueueDiff};
+use crate::error::{CommandError, InboundError};
+use crate::invocations::PendingInvocations;
+use crate::outbox::{Outbox, OutboxCommand, PendingCommand};
+use crate::protocol::{HubInvocation, HubMessage, ServerMessage, NO_ARGS};
+use crate::queue::{Message, Queue, User};
+use crate::settings::Settings;
+use crate::transport::Inbound;
+use crate::writer::Writer;
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened to the client, as seen by [`QHubClient::subscribe`]
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    ConnectionStatus(ConnectionStatus),
+    /// Every queue, freshly listed from the server
+    QueuesListed(BTreeMap<u64, Queue>),
+    /// A single queue was announced by the server
+    QueueChanged {
+        change: QueueChange,
+        queue: Queue,
+    },
+    /// Someone nagged the members of a queue
+    Nagged {
+        queue: Queue,
+        /// Whoever nagged, if the server said
+        by: Option<User>,
+    },
+    /// Someone sent a message to a queue, which has already been added to its messages
+    MessageSent {
+        queue: Queue,
+        message: Message,
+    },
+    /// What changed about the queues, whichever of the events above changed them
+    QueuesDiffed(Vec<QueueDiff>),
+    /// Every command still waiting to be sent, after one was added to or sent from the outbox
+    OutboxChanged(Vec<PendingCommand>),
+}
+
+/// Which notification a [`ClientEvent::QueueChanged`] came from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QueueChange {
+    Created,
+    StatusChanged,
+    MembershipChanged,
+}
+
+/// Everything shared between the connection manager and the handles to a client
+#[derive(Debug)]
+pub(crate) struct ClientState {
+    pub(crate) settings: RwLock<Settings>,
+    pub(crate) message_number: AtomicU64,
+    pub(crate) queues: RwLock<BTreeMap<u64, Queue>>,
+    /// Set while the queues are the ones cached last time, rather than listed from the server
+    stale: AtomicBool,
+    cache: Option<QueueCache>,
+    pub(crate) outbox: Outbox,
+    pub(crate) pending_invocations: PendingInvocations,
+    /// Writes to the current connection, if there is one
+    pub(crate) writer: RwLock<Option<Writer>>,
+    pub(crate) connection: RwLock<ConnectionInfo>,
+    pub(crate) session: RwLock<Option<Session>>,
+    pub(crate) reconnect: Notify,
+    /// Notified when the server asks us to reconnect
+    pub(crate) resume: Notify,
+    /// Notified when notifications may have been missed, so the queues need listing again
+    pub(crate) resync: Notify,
+    /// Notified when a command joins the outbox while connected, in case the replay has stopped
+    pub(crate) replay: Notify,
+    events: broadcast::Sender<ClientEvent>,
+}
+
+impl ClientState {
+    pub(crate) fn emit(&self, event: ClientEvent) {
+        // Nobody listening is fine, the state is still kept up to date
+        let _ = self.events.send(event);
+    }
+
+    /// Lets subscribers know what changed about the queues, if anything did, and caches them
+    async fn queues_changed(&self, diffs: Vec<QueueDiff>) {
+        if diffs.is_empty() {
+            return;
+        }
+        self.emit(ClientEvent::QueuesDiffed(diffs));
+
+        if let Some(cache) = &self.cache {
+            let _ = cache
+                .store(&self.queues)
+                .await
+                .inspect_err(|e| warn!("Couldn't cache queues: {:#}", e));
+        }
+    }
+}
+
+/// A handle to a QHub client. Clones share the same connection and state.
+#[derive(Debug, Clone)]
+pub struct QHubClient {
+    state: Arc<ClientState>,
+}
+
+impl QHubClient {
+    /// A client for the server in `settings`, which doesn't connect until [`Self::spawn`]ed
+    pub fn new(settings: Settings) -> Self {
+        Self::with_optional_data_dir(settings, None)
+    }
+
+    /// Like [`Self::new`], but keeps the queues and the outbox in `dir` between runs. It starts
+    /// out with the queues cached there last time, which are [stale](Self::queues_stale) until
+    /// they've been listed from the server, and with whatever was left in the outbox.
+    pub fn with_data_dir(settings: Settings, dir: impl Into<PathBuf>) -> Self {
+        Self::with_optional_data_dir(settings, Some(dir.into()))
+    }
+
+    fn with_optional_data_dir(settings: Settings, dir: Option<PathBuf>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        let cache = dir
+            .as_ref()
+            .map(|dir| QueueCache::new(dir.join("queues.json")));
+        let outbox = Outbox::new(dir.map(|dir| dir.join("outbox.json")));
+
+        // Starting out with no queues is fine, they'll be listed once connected
+        let cached = cache
+            .as_ref()
+            .and_then(|cache| {
+                cache
+                    .load()
+                    .inspect_err(|e| warn!("{:#}, starting without cached queues", e))
+                    .ok()
+            })
+            .flatten();
+
+        Self {
+            state: Arc::new(ClientState {
+                settings: RwLock::new(settings),
+                message_number: AtomicU64::default(),
+                stale: AtomicBool::new(cached.is_some()),
+                queues: RwLock::new(cached.unwrap_or_default()),
+                cache,
+                outbox,
+                pending_invocations: PendingInvocations::default(),
+                writer: RwLock::default(),
+                connection: RwLock::default(),
+                session: RwLock::default(),
+                reconnect: Notify::new(),
+                resume: Notify::new(),
+                resync: Notify::new(),
+                replay: Notify::new(),
+                events,
+            }),
+        }
+    }
+
+    /// Starts the connection manager, which keeps the client connected until the runtime stops
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let state = self.state.clone();
+        tokio::spawn(async move { connection::run(&state).await })
+    }
+
+    /// Events from now on. Subscribe before spawning to see the first connection attempt.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.state.events.subscribe()
+    }
+
+    pub async fn queues(&self) -> BTreeMap<u64, Queue> {
+        self.state.queues.read().await.clone()
+    }
+
+    /// Whether the queues are still the ones cached last time, because none have been listed from
+    /// the server yet
+    pub fn queues_stale(&self) -> bool {
+        self.state.stale.load(Ordering::Relaxed)
+    }
+
+    /// Commands waiting to be sent once connected, oldest first
+    pub async fn outbox(&self) -> Vec<PendingCommand> {
+        self.state.outbox.commands().await
+    }
+
+    pub async fn connection_status(&self) -> ConnectionStatus {
+        self.state.connection.read().await.status()
+    }
+
+    pub async fn settings(&self) -> Settings {
+        self.state.settings.read().await.clone()
+    }
+
+    /// Switches to new settings, reconnecting straight away so they take effect. Invalid settings
+    /// are refused, leaving the current ones in place.
+    pub async fn update_settings(&self, settings: Settings) -> Result<(), CommandError> {
+        settings.validate().map_err(|e| {
+            warn!("Refusing invalid settings: {:#}", e);
+            CommandError::invalid_input(e)
+        })?;
+        *self.state.settings.write().await = settings;
+        self.request_reconnect();
+
+        Ok(())
+    }
+
+    /// Drops the current connection, if any, and connects again straight away
+    pub fn request_reconnect(&self) {
+        connection::request_reconnect(&self.state);
+    }
+
+    /// Tells the server we're going away, so it can clean up our connection straight away
+    pub async fn shutdown(&self) {
+        connection::shutdown(&self.state).await;
+    }
+
+    /// Runs a command straight away if connected and nothing is waiting in the outbox.
+    /// Otherwise it joins the outbox, and is sent after everything issued before it once the
+    /// connection is back.
+    pub async fn run_or_queue(&self, command: OutboxCommand) -> Result<(), CommandError> {
+        let state = &self.state;
+        let connected = async { state.writer.read().await.is_some() };
+        let Some(commands) = state.outbox.push_unless_sendable(&command, connected).await else {
+            return run(state, &command).await;
+        };
+
+        debug!("Added {:?} to the outbox", command);
+        state.emit(ClientEvent::OutboxChanged(commands));
+        state.replay.notify_one();
+        Ok(())
+    }
+
+    /// Invokes a hub method with `args`, which must serialize to an array, and returns what it
+    /// returns. Use [`serde::de::IgnoredAny`] for methods whose result doesn't matter.
+    pub async fn invoke<A, R>(&self, method: &str, args: A) -> Result<R, CommandError>
+    where
+        A: Serialize,
+        R: DeserializeOwned,
+    {
+        invoke(&self.state, method, args).await
+    }
+
+    pub async fn leave_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::LeaveQueue { id }).await
+    }
+
+    pub async fn join_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::JoinQueue { id }).await
+    }
+
+    pub async fn message_queue(&self, id: u64, content: &str) -> Result<(), CommandError> {
+        let content = content.to_owned();
+        run(&self.state, &OutboxCommand::MessageQueue { id, content }).await
+    }
+
+    pub async fn start_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::StartQueue { id }).await
+    }
+
+    pub async fn reset_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::ResetQueue { id }).await
+    }
+
+    pub async fn nag_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::NagQueue { id }).await
+    }
+
+    pub async fn delete_queue(&self, id: u64) -> Result<(), CommandError> {
+        run(&self.state, &OutboxCommand::DeleteQueue { id }).await
+    }
+
+    pub async fn new_queue(
+        &self,
+        name: &str,
+        restrict_to_group: Option<&str>,
+    ) -> Result<(), CommandError> {
+        let command = OutboxCommand::NewQueue {
+            name: name.to_owned(),
+            restrict_to_group: restrict_to_group.map(str::to_owned),
+        };
+        run(&self.state, &command).await
+    }
+}
+
+/// Invokes the hub method behind a command. Commands run straight away and from the outbox both
+/// go through here, so they can't disagree about what to send.
+async fn run(state: &ClientState, command: &OutboxCommand) -> Result<(), CommandError> {
+    let (method, args) = command.invocation();
+    invoke::<_, IgnoredAny>(state, method, args).await?;
+    Ok(())
+}
+
+/// Invokes a hub method, returning whatever it returns. Everything sent to the server goes
+/// through here, so each invocation gets its own ID and is abandoned if the connection resets.
+#[tracing::instrument(skip(state, args), level = "debug")]
+async fn invoke<A, R>(state: &ClientState, method: &str, args: A) -> Result<R, CommandError>
+where
+    A: Serialize,
+    R: DeserializeOwned,
+{
+    let writer = state
+        .writer
+        .read()
+        .await
+        .clone()
+        .ok_or(CommandError::NotConnected)?;
+    let invocation_id = state.message_number.fetch_add(1, Ordering::Relaxed);
+    // Registered before sending, so a fast reply can't arrive unclaimed
+    let pending = state.pending_invocations.register(invocation_id);
+
+    let body = HubInvocation::new(invocation_id, method, args)
+        .to_json()
+        .map_err(CommandError::invalid_input)?;
+    writer.send(body).await.map_err(send_failed)?;
+    debug!("Sent invocation {}", invocation_id);
+
+    let result = pending.wait().await?;
+    serde_json::from_value(result).map_err(|e| CommandError::HubError {
+        message: format!("Unexpected {} result: {}", method, e),
+    })
+}
+
+/// Sending only fails once the connection is broken, so the invocation won't get through
+fn send_failed(error: anyhow::Error) -> CommandError {
+    debug!("Couldn't send invocation: {:#}", error);
+    CommandError::Cancelled
+}
+
+/// Sends what was issued while disconnected, oldest first, until the outbox is empty. Stops at
+/// the first command that couldn't be sent, which stays in the outbox for next time.
+pub(crate) async fn replay_outbox(state: &ClientState) {
+    while let Some(pending) = state.outbox.start_sending().await {
+        match run(state, &pending.command).await {
+            Ok(_) => debug!("Sent {:?} from the outbox", pending.command),
+            // Sending it again won't change the server's mind
+            Err(e @ CommandError::HubError { .. }) => {
+                warn!(
+                    "Server refused {:?} from the outbox: {:#}",
+                    pending.command, e
+                )
+            }
+            Err(e) => {
+                warn!(
+                    "Couldn't send {:?} from the outbox: {:#}",
+                    pending.command, e
+                );
+                state.outbox.stop_sending().await;
+                return;
+            }
+        }
+
+        let commands = state.outbox.remove(pending.id).await;
+        state.emit(ClientEvent::OutboxChanged(commands));
+    }
+}
+
+#[tracing::instrument(skip(state), level = "debug")]
+pub(crate) async fn list_all_queues(state: &ClientState) {
+    match invoke::<_, Value>(state, "ListQueues", NO_ARGS).await {
+        Ok(result) => {
+            let _ = update_queues(state, result)
+                .await
+                .inspect_err(|e| warn!("Couldn't update queues: {:#}", e));
+        }
+        // They're listed again as soon as there's a new connection
+        Err(CommandError::NotConnected | CommandError::Cancelled) => {}
+        Err(e) => warn!("Couldn't list queues: {}", e),
+    }
+}
+
+/// Replaces every known queue with the result of a `ListQueues` invocation
+async fn update_queues(state: &ClientState, result: Value) -> Result<(), InboundError> {
+    let queues: BTreeMap<u64, Queue> = serde_json::from_value::<Vec<Value>>(result)
+        .map_err(|source| InboundError::Malformed {
+            what: "ListQueues result",
+            source,
+        })?
+        .into_iter()
+        .filter_map(|queue| {
+            Queue::from_value(queue)
+                .inspect_err(|e| warn!("Skipping queue: {}", e))
+                .ok()
+        })
+        .map(|queue| (queue.id, queue))
+        .collect();
+
+    let diffs = {
+        let mut current = state.queues.write().await;
+        let diffs = diff_queues(&current, &queues);
+        *current = queues.clone();
+        diffs
+    };
+    state.stale.store(false, Ordering::Relaxed);
+    state.queues_changed(diffs).await;
+    state.emit(ClientEvent::QueuesListed(queues));
+
+    Ok(())
+}
+
+pub(crate) async fn handle_inbound(
+    state: &ClientState,
+    inbound: Inbound,
+) -> Result<(), InboundError> {
+    match inbound {
+        Inbound::Text(body) => parse_message(state, ServerMessage::parse(&body)?).await,
+        Inbound::KeepAlive => Ok(()),
+        Inbound::Invalid(e) => Err(e),
+    }
+}
+
+#[tracing::instrument(skip(state, message), level = "debug")]
+async fn parse_message(state: &ClientState, message: ServerMessage) -> Result<(), InboundError> {
+    match message {
+        ServerMessage::Result(result) => {
+            let message_id = result.id;
+            if !state.pending_invocations.resolve(result) {
+                debug!("Nobody waiting on result for invocation {}", message_id);
+            }
+        }
+        ServerMessage::Frame(frame) => {
+            if let Some(error) = &frame.error {
+                warn!("Server sent error: {}", error);
+            }
+
+            if let Some(session) = state.session.write().await.as_mut() {
+                session.advance(&frame);
+            }
+            if frame.should_reconnect == Some(1) {
+                debug!("Server asked us to reconnect");
+                state.resume.notify_waiters();
+            }
+
+            for notification in frame.messages {
+                let _ = handle_notification(state, notification)
+                    .await
+                    .inspect_err(|e| {
+                        warn!("Skipping notification, and resyncing: {}", e);
+                        state.resync.notify_one();
+                    });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_notification(
+    state: &ClientState,
+    notification: HubMessage,
+) -> Result<(), InboundError> {
+    let change = match notification.method.as_str() {
+        "NewQueue" => QueueChange::Created,
+        "QueueStatusChanged" => QueueChange::StatusChanged,
+        "QueueMembershipChanged" => QueueChange::MembershipChanged,
+        "NagQueue" => {
+            debug!("Nag arguments {:#?}", notification.args);
+            let mut args = notification.args.into_iter();
+            let queue =
+                Queue::from_value(args.next().ok_or_else(|| InboundError::MissingArguments {
+                    method: notification.method.clone(),
+                })?)?;
+            let by = args.next().map(User::from_value).transpose()?;
+            state.emit(ClientEvent::Nagged { queue, by });
+            return Ok(());
+        }
+        "QueueMessageSent" => return handle_message_sent(state, notification).await,
+        _ => return Ok(()),
+    };
+
+    debug!("Processing changed queue");
+    let updated_queue =
+        Queue::from_value(notification.args.first().cloned().ok_or_else(|| {
+            InboundError::MissingArguments {
+                method: notification.method.clone(),
+            }
+        })?)?;
+    let old_queue = state
+        .queues
+        .write()
+        .await
+        .insert(updated_queue.id, updated_queue.clone());
+    if old_queue.is_none() && change != QueueChange::Created {
+        debug!(
+            "Missed the creation of queue {}, resyncing",
+            updated_queue.id
+        );
+        state.resync.notify_one();
+    }
+    let diffs = diff_queue(old_queue.as_ref(), &updated_queue);
+    state.emit(ClientEvent::QueueChanged {
+        change,
+        queue: updated_queue,
+    });
+    state.queues_changed(diffs).await;
+
+    Ok(())
+}
+
+/// Adds a message to its queue. The server sends the queue's ID followed by the message.
+async fn handle_message_sent(
+    state: &ClientState,
+    notification: HubMessage,
+) -> Result<(), InboundError> {
+    let missing_arguments = || InboundError::MissingArguments {
+        method: notification.method.clone(),
+    };
+    let mut args = notification.args.iter().cloned();
+    let id = serde_json::from_value::<u64>(args.next().ok_or_else(missing_arguments)?).map_err(
+        |source| InboundError::Malformed {
+            what: "queue ID",
+            source,
+        },
+    )?;
+    let message = Message::from_value(args.next().ok_or_else(missing_arguments)?)?;
+
+    let queue = {
+        let mut queues = state.queues.write().await;
+        let queue = queues.get_mut(&id).ok_or(InboundError::UnknownQueue(id))?;
+        queue.messages.push(message.clone());
+        queue.clone()
+    };
+    state.emit(ClientEvent::MessageSent {
+        queue,
+        message: message.clone(),
+    });
+    state
+        .queues_changed(vec![QueueDiff::MessageAppended { id, message }])
+        .await;
+
+    Ok(())
+}
@@ -1,345 +1,145 @@
-use std::sync::atomic::Ordering;
-
-use crate::{connect, new_queue::NewQueueApp, settings::Settings, RequestType, State};
-use futures_util::sink::SinkExt;
-use serde_json::json;
+use rq::{
+    CommandError, ConnectionStatus, History, HistoryEntry, HistoryQuery, OutboxCommand, QHubClient,
+    Settings,
+};
 use tauri::Manager;
-use tokio::select;
-use tokio_tungstenite::tungstenite::Message as WebsocketMessage;
-use tracing::log::debug;
-
-#[tauri::command]
-pub(crate) async fn fetch_data(
-    app_handle: tauri::AppHandle,
-    state: tauri::State<'_, State>,
-) -> Result<(), ()> {
-    let queues = &*state.queues.read().await;
-    let config = fetch_settings().await?;
 
-    app_handle
-        .emit_all(
-            "data_updated",
-            json!(
-                    {
-                        "queues": queues,
-                        "config": config,
-                    }
-            ),
-        )
-        .unwrap();
+use crate::{emit_queues, new_queue::NewQueueApp, settings_window};
 
-    Ok(())
+#[tauri::command]
+pub(crate) async fn fetch_data(app_handle: tauri::AppHandle) -> Result<(), CommandError> {
+    emit_queues(&app_handle).await.map_err(CommandError::ui)
 }
 
 #[tauri::command]
-pub(crate) async fn leave_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::LeaveQueue,
-    );
-
-    select!(
-    _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                {
-                    "I": message_number.fetch_add(1, Ordering::Relaxed),
-                    "H": "QHub",
-                    "M": "LeaveQueue",
-                    "A": [id]
-                }
-            }
-            .to_string(),
-        )) => { },
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-
-    Ok(())
+pub(crate) async fn leave_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::LeaveQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
-pub(crate) async fn join_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    debug!("Joining queue...");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::JoinQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-    _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                    {
-                        "I": message_number.fetch_add(1, Ordering::Relaxed),
-                        "H": "QHub",
-                        "M": "JoinQueue",
-                        "A": [id]
-                    }
-            }
-            .to_string(),
-        )) => { },
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-
-    debug!("Join request sent!");
-    Ok(())
+pub(crate) async fn join_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::JoinQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
 pub(crate) async fn message_queue(
-    state: tauri::State<'_, State>,
+    client: tauri::State<'_, QHubClient>,
     id: u64,
     content: &str,
-) -> Result<(), ()> {
-    debug!("Sending message to queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::MessageQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-            .send(WebsocketMessage::text(
-                json! {
-                        {
-                            "I": message_number.fetch_add(1, Ordering::Relaxed),
-                            "H": "QHub",
-                            "M": "MessageQueue",
-                            "A": [id, content]
-                        }
-                }
-                .to_string(),
-            ) ) => {},
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-    debug!("Queue message sent!");
-    Ok(())
+) -> Result<(), CommandError> {
+    client
+        .run_or_queue(OutboxCommand::MessageQueue {
+            id,
+            content: content.to_owned(),
+        })
+        .await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
-pub(crate) async fn start_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    debug!("Starting queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::StartQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-            .send(WebsocketMessage::text(
-                json! {
-                        {
-                            "I": message_number.fetch_add(1, Ordering::Relaxed),
-                            "H": "QHub",
-                            "M": "ActivateQueue",
-                            "A": [id]
-                        }
-                }
-                .to_string(),
-            )) => {},
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-    debug!("Queue started");
-    Ok(())
+pub(crate) async fn start_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::StartQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
-pub(crate) async fn reset_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    debug!("Resetting queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::ResetQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                    {
-                        "I": message_number.fetch_add(1, Ordering::Relaxed),
-                        "H": "QHub",
-                        "M": "DeactivateQueue",
-                        "A": [id]
-                    }
-            }
-            .to_string(),
-            )) => {},
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-    debug!("Queue reset");
-    Ok(())
+pub(crate) async fn reset_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::ResetQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
-pub(crate) async fn nag_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    debug!("Nagging queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::NagQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                    {
-                        "I": message_number.fetch_add(1, Ordering::Relaxed),
-                        "H": "QHub",
-                        "M": "NagQueue",
-                        "A": [id]
-                    }
-            }
-            .to_string(),
-            )) => {},
-        _ = state.cancel_websockets.notified() => { return Err(()) }
-    );
-    debug!("Queue nagged");
-    Ok(())
+pub(crate) async fn nag_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::NagQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
-pub(crate) async fn delete_queue(state: tauri::State<'_, State>, id: u64) -> Result<(), ()> {
-    debug!("Deleting queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::DeleteQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                    {
-                        "I": message_number.fetch_add(1, Ordering::Relaxed),
-                        "H": "QHub",
-                        "M": "CloseQueue",
-                        "A": [id]
-                    }
-            }
-            .to_string(),
-            )) => {},
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-    debug!("Queue deleted");
-    Ok(())
+pub(crate) async fn delete_queue(
+    client: tauri::State<'_, QHubClient>,
+    id: u64,
+) -> Result<(), CommandError> {
+    client.run_or_queue(OutboxCommand::DeleteQueue { id }).await
 }
 
-#[tracing::instrument(level = "debug")]
 #[tauri::command]
 pub(crate) async fn new_queue(
-    state: tauri::State<'_, State>,
+    client: tauri::State<'_, QHubClient>,
     name: &str,
     restrict_to_group: Option<&str>,
-) -> Result<(), ()> {
-    debug!("Starting queue");
-    let mut websocket = state.websocket_tx.lock().await;
-    let websocket = websocket.as_mut().ok_or(())?;
-    let message_number = &state.message_number;
-
-    debug!("Logging request type");
-    state.response_type.write().await.insert(
-        message_number.load(Ordering::Relaxed),
-        RequestType::NewQueue,
-    );
-    debug!("Logged request type request type");
-
-    select!(
-        _ = websocket
-        .send(WebsocketMessage::text(
-            json! {
-                    {
-                        "I": message_number.fetch_add(1, Ordering::Relaxed),
-                        "H": "QHub",
-                        "M": "StartQueue",
-                        "A": [name, restrict_to_group.unwrap_or("")]
-                    }
-            }
-            .to_string(),
-            )) => {},
-        _ = state.cancel_websockets.notified() => {return Err(())}
-    );
-    debug!("Queue created");
-    Ok(())
+) -> Result<(), CommandError> {
+    client
+        .run_or_queue(OutboxCommand::NewQueue {
+            name: name.to_owned(),
+            restrict_to_group: restrict_to_group.map(ToOwned::to_owned),
+        })
+        .await
 }
 
 #[tauri::command]
-pub(crate) async fn fetch_settings() -> Result<Settings, ()> {
-    let settings = confy::load("rq", None).map_err(|_e| ())?;
-
-    Ok(settings)
+pub(crate) async fn fetch_settings() -> Result<Settings, CommandError> {
+    Settings::load().map_err(CommandError::settings)
 }
 
 #[tauri::command]
 pub(crate) async fn write_settings(
-    app: tauri::AppHandle,
-    state: tauri::State<'_, State>,
+    client: tauri::State<'_, QHubClient>,
     settings: Settings,
-) -> Result<(), ()> {
-    confy::store("rq", None, settings).map_err(|_e| ())?;
+) -> Result<(), CommandError> {
+    // Applied first, as that validates them, so invalid settings are never stored
+    client.update_settings(settings.clone()).await?;
 
-    state.cancel_websockets.notify_waiters();
-    let _ = connect(app.clone()).await;
+    settings.store().map_err(CommandError::settings)
+}
 
-    Ok(())
+#[tauri::command]
+pub(crate) async fn fetch_connection_status(
+    client: tauri::State<'_, QHubClient>,
+) -> Result<ConnectionStatus, CommandError> {
+    Ok(client.connection_status().await)
+}
+
+#[tauri::command]
+pub(crate) async fn fetch_history(
+    app: tauri::AppHandle,
+    query: HistoryQuery,
+) -> Result<Vec<HistoryEntry>, CommandError> {
+    let history = app
+        .try_state::<History>()
+        .ok_or_else(|| CommandError::History {
+            message: "History isn't being kept".to_owned(),
+        })?;
+
+    history
+        .query(&query)
+        .await
+        .map_err(|e| CommandError::History {
+            message: format!("{:#}", e),
+        })
 }
 
 #[tauri::command]
-pub(crate) async fn open_settings(app: tauri::AppHandle) -> Result<(), ()> {
-    let settings = confy::load::<Settings>("rq", None).map_err(|_e| ())?;
+pub(crate) async fn open_settings(app: tauri::AppHandle) -> Result<(), CommandError> {
+    let settings = Settings::load().map_err(CommandError::settings)?;
 
-    settings.launch(app);
+    settings_window::launch(settings, app);
 
     Ok(())
 }
 
 #[tauri::command]
-pub(crate) async fn open_new_queue(app: tauri::AppHandle) -> Result<(), ()> {
+pub(crate) async fn open_new_queue(app: tauri::AppHandle) -> Result<(), CommandError> {
     NewQueueApp::launch(app);
 
     Ok(())
@@ -0,0 +1,710 @@
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{FutureExt, StreamExt};
+use native_tls::TlsConnector;
+use rand::Rng;
+use reqwest::header::{
+    ACCEPT, CONNECTION, HOST, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use reqwest::{header::USER_AGENT, Method, Version};
+use reqwest::{Body, RequestBuilder, Url};
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::tungstenite::handshake::client::{generate_key, Request};
+use tokio_tungstenite::tungstenite::http::request::Parts;
+use tokio_tungstenite::Connector;
+use tokio_util::sync::CancellationToken;
+use tracing::log::{debug, warn};
+use tracing::{debug_span, Instrument};
+
+use crate::client::{handle_inbound, list_all_queues, replay_outbox, ClientEvent, ClientState};
+use crate::endpoint::ServerEndpoint;
+use crate::protocol::{
+    connection_data, ConnectionFrame, NegotiateResponse, ServerMessage, PROTOCOL_VERSION,
+};
+use crate::settings::Settings;
+use crate::tls;
+use crate::transport::{Inbound, Transport, TransportKind, TransportReceiver, Websocket};
+use crate::writer::Writer;
+
+const USER_AGENT_VALUE: &str = "SignalR.Client.Net45/2.2.0.0 (Microsoft Windows NT 6.2.9200.0)";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long the server gets to send the initialization frame after the websocket opens, unless
+/// it negotiated a different transport connect timeout
+const INIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long shutting down may be held up telling the server we're going
+const ABORT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long the server holds on to a poll with nothing to say, unless it negotiated otherwise
+const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(110);
+/// Allowance on top of the connection timeout before a long poll is considered lost
+const LONG_POLL_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Negotiating,
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        #[serde(rename = "retryInMs", serialize_with = "serialize_millis")]
+        retry_in: Duration,
+    },
+}
+
+/// Everything the UI needs to know about the connection, as tracked by [`run`]
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionInfo {
+    state: ConnectionState,
+    last_error: Option<String>,
+    server_url: Option<String>,
+    last_message_at: Option<Instant>,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn status(&self) -> ConnectionStatus {
+        ConnectionStatus {
+            state: self.state,
+            last_error: self.last_error.clone(),
+            server_url: self.server_url.clone(),
+            since_last_message: self.last_message_at.map(|at| at.elapsed()),
+        }
+    }
+}
+
+/// A snapshot of the connection, as reported by [`ClientEvent::ConnectionStatus`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub last_error: Option<String>,
+    pub server_url: Option<String>,
+    #[serde(
+        rename = "sinceLastMessageMs",
+        serialize_with = "serialize_optional_millis"
+    )]
+    pub since_last_message: Option<Duration>,
+}
+
+impl ConnectionStatus {
+    /// One line description, as shown in the tray menu
+    pub fn summary(&self) -> String {
+        let server = self.server_url.as_deref().unwrap_or("server");
+
+        match self.state {
+            ConnectionState::Disconnected => "Disconnected".to_owned(),
+            ConnectionState::Negotiating => format!("Connecting to {}…", server),
+            ConnectionState::Connected => format!("Connected to {}", server),
+            ConnectionState::Reconnecting { retry_in, .. } => {
+                // Round up, so the countdown never shows 0s while still waiting
+                format!("Reconnecting in {}s", (retry_in.as_millis() + 999) / 1000)
+            }
+        }
+    }
+}
+
+/// Why a connection stopped being served
+enum Disconnect {
+    Lost(anyhow::Error),
+    /// The server asked us to reconnect, resuming where we left off
+    ResumeRequested,
+    /// Settings changed, so start a new connection from scratch
+    ReconnectRequested,
+}
+
+/// Keeps the client connected for as long as it runs, reconnecting with exponential backoff
+/// whenever the connection drops or can't be established.
+pub(crate) async fn run(state: &ClientState) {
+    let mut attempt: u32 = 0;
+
+    loop {
+        let connected = async {
+            let settings = state.settings.read().await.clone();
+            state.connection.write().await.server_url = Some(settings.server_url.clone());
+            set_connection_state(state, ConnectionState::Negotiating).await;
+
+            let connection = PersistentConnection::new(&settings)?;
+            let (transport, keep_alive) = resume_or_connect(state, &connection).await?;
+            Ok((connection, transport, keep_alive))
+        }
+        .await;
+
+        match connected {
+            Ok((connection, transport, keep_alive)) => {
+                attempt = 0;
+                let disconnect = serve(state, transport, keep_alive).await;
+                set_connection_state(state, ConnectionState::Disconnected).await;
+
+                match disconnect {
+                    Disconnect::Lost(e) => {
+                        warn!("Connection lost: {:#}", e);
+                        record_error(state, &e).await;
+                    }
+                    Disconnect::ResumeRequested => continue,
+                    Disconnect::ReconnectRequested => {
+                        // The old connection belongs to the old settings, so don't resume it
+                        if let Some(session) = state.session.write().await.take() {
+                            let _ = connection
+                                .abort(&session)
+                                .await
+                                .inspect_err(|e| warn!("Couldn't abort connection: {:#}", e));
+                        }
+                        continue;
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Error when connecting to server: {:#}", e);
+                record_error(state, &e).await;
+            }
+        }
+
+        attempt = attempt.saturating_add(1);
+        let retry_in = backoff(attempt);
+        debug!("Reconnecting in {:?} (attempt {})", retry_in, attempt);
+
+        select! {
+            _ = count_down(state, attempt, retry_in) => {}
+            _ = state.reconnect.notified() => {
+                // The old connection belongs to the old settings, so don't resume it
+                *state.session.write().await = None;
+            }
+        }
+    }
+}
+
+/// Resumes the previous connection if there is one, so no notifications are missed, and
+/// negotiates a new one otherwise
+async fn resume_or_connect(
+    state: &ClientState,
+    connection: &PersistentConnection,
+) -> Result<(Transport, KeepAlive)> {
+    let session = state.session.read().await.clone();
+
+    if let Some(session) = session {
+        match connection.reconnect(&session).await {
+            Ok(transport) => return Ok((transport, session.keep_alive)),
+            Err(e) => warn!("Couldn't resume connection, starting a new one: {:#}", e),
+        }
+    }
+
+    let (transport, session) = connection.connect().await?;
+    let keep_alive = session.keep_alive;
+    *state.session.write().await = Some(session);
+
+    Ok((transport, keep_alive))
+}
+
+/// Waits out a backoff delay, updating the state every second so the UI can show a countdown
+async fn count_down(state: &ClientState, attempt: u32, retry_in: Duration) {
+    let retry_at = tokio::time::Instant::now() + retry_in;
+
+    loop {
+        let retry_in = retry_at.saturating_duration_since(tokio::time::Instant::now());
+        if retry_in.is_zero() {
+            return;
+        }
+
+        set_connection_state(state, ConnectionState::Reconnecting { attempt, retry_in }).await;
+        sleep(retry_in.min(Duration::from_secs(1))).await;
+    }
+}
+
+/// Tells the server we're going away, so it can clean up our connection straight away
+pub(crate) async fn shutdown(state: &ClientState) {
+    let Some(session) = state.session.write().await.take() else {
+        return;
+    };
+
+    let aborted = async {
+        let settings = state.settings.read().await.clone();
+        PersistentConnection::new(&settings)?.abort(&session).await
+    }
+    .await;
+
+    match aborted {
+        Ok(()) => debug!("Connection aborted"),
+        Err(e) => warn!("Couldn't abort connection: {:#}", e),
+    }
+}
+
+/// Drops the current connection, if any, and connects again straight away. Also cuts short any
+/// backoff delay, so new settings take effect immediately.
+pub(crate) fn request_reconnect(state: &ClientState) {
+    state.reconnect.notify_one();
+}
+
+async fn set_connection_state(state: &ClientState, connection_state: ConnectionState) {
+    debug!("Connection state: {:?}", connection_state);
+
+    let status = {
+        let mut connection = state.connection.write().await;
+        connection.state = connection_state;
+        connection.status()
+    };
+
+    state.emit(ClientEvent::ConnectionStatus(status));
+}
+
+async fn record_error(state: &ClientState, error: &anyhow::Error) {
+    state.connection.write().await.last_error = Some(format!("{:#}", error));
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+fn serialize_optional_millis<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_millis(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Exponential backoff with equal jitter: half the delay is fixed and the other half random, so
+/// clients that lost the server at the same moment don't all come back at the same moment.
+fn backoff(attempt: u32) -> Duration {
+    let exponential =
+        INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+    let half = exponential.min(MAX_BACKOFF) / 2;
+
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+/// Identifies a connection on the server and how far we've read from it, so a dropped connection
+/// can be resumed without missing any messages.
+#[derive(Debug, Clone)]
+pub(crate) struct Session {
+    connection_token: String,
+    transport: TransportKind,
+    pub(crate) message_id: Option<String>,
+    pub(crate) groups_token: Option<String>,
+    keep_alive: KeepAlive,
+}
+
+impl Session {
+    /// Moves the cursor past a frame that has been received
+    pub(crate) fn advance(&mut self, frame: &ConnectionFrame) {
+        if let Some(message_id) = &frame.message_id {
+            self.message_id = Some(message_id.clone());
+        }
+        if let Some(groups_token) = &frame.groups_token {
+            self.groups_token = Some(groups_token.clone());
+        }
+    }
+}
+
+/// How a connection is kept alive, as negotiated with the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeepAlive {
+    /// A connection that hasn't received anything for this long is considered dead
+    timeout: Duration,
+    ping_interval: Duration,
+}
+
+impl KeepAlive {
+    fn negotiated(negotiated: &NegotiateResponse, transport: TransportKind) -> Self {
+        // With keep-alives off the server can be quiet for as long as it likes, but it still
+        // answers our pings, so only give up once it would have forgotten the connection anyway
+        let timeout = negotiated
+            .keep_alive_timeout
+            .unwrap_or(negotiated.disconnect_timeout);
+        // Leave room for a couple of pings to go unanswered before the connection is dropped
+        let ping_interval = timeout / 3;
+
+        let timeout = match transport {
+            // Polls get no keep-alives, and are held open until there's news or they time out
+            TransportKind::LongPolling => {
+                negotiated
+                    .connection_timeout
+                    .unwrap_or(DEFAULT_CONNECTION_TIMEOUT)
+                    + LONG_POLL_GRACE
+            }
+            TransportKind::WebSockets | TransportKind::ServerSentEvents => timeout,
+        };
+
+        Self {
+            timeout,
+            ping_interval,
+        }
+    }
+}
+
+/// The HTTP side of a SignalR persistent connection, used to open, resume and abort connections to
+/// the server configured in one set of settings.
+#[derive(Debug, Clone)]
+pub(crate) struct PersistentConnection {
+    endpoint: ServerEndpoint,
+    client: reqwest::Client,
+    tls_connector: TlsConnector,
+    user_header: String,
+    /// Only try this transport, rather than falling back through all of them
+    forced_transport: Option<TransportKind>,
+}
+
+impl PersistentConnection {
+    pub(crate) fn new(settings: &Settings) -> Result<Self> {
+        let endpoint = settings.server_endpoint()?;
+        let tls_connector = tls::tls_connector(settings)?;
+        if settings.accept_invalid_certs {
+            warn!("Certificate validation is disabled for {}", endpoint.base());
+        }
+
+        let client = reqwest::Client::builder()
+            .use_preconfigured_tls(tls_connector.clone())
+            .build()?;
+
+        Ok(Self {
+            endpoint,
+            client,
+            tls_connector,
+            user_header: format!(
+                "{};{};{}",
+                settings.username, settings.full_name, settings.email
+            ),
+            forced_transport: settings.transport,
+        })
+    }
+
+    pub(crate) fn endpoint(&self) -> &ServerEndpoint {
+        &self.endpoint
+    }
+
+    /// Negotiates a new connection, then opens and starts the first transport that works
+    pub(crate) async fn connect(&self) -> Result<(Transport, Session)> {
+        debug!(
+            "Attempting to initiate connection to {}",
+            self.endpoint.base()
+        );
+
+        let negotiated = self
+            .request(Method::GET, self.endpoint.negotiate_url())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<NegotiateResponse>()
+            .await
+            .context("Couldn't parse negotiate response")?;
+
+        negotiated.check_protocol_version()?;
+
+        let transports = match self.forced_transport {
+            Some(transport) => vec![transport],
+            None => TransportKind::ALL.to_vec(),
+        };
+
+        let mut last_error = None;
+        for transport in transports {
+            let mut session = Session {
+                connection_token: negotiated.connection_token.clone(),
+                transport,
+                message_id: None,
+                groups_token: None,
+                keep_alive: KeepAlive::negotiated(&negotiated, transport),
+            };
+
+            match self.start_transport(&negotiated, &mut session).await {
+                Ok(opened) => {
+                    debug!("Connected using {}", transport);
+                    return Ok((opened, session));
+                }
+                Err(e) => {
+                    warn!("Couldn't connect using {}: {:#}", transport, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No transports to connect with")))
+    }
+
+    /// Reopens the transport of an existing connection, picking up after the last message received
+    pub(crate) async fn reconnect(&self, session: &Session) -> Result<Transport> {
+        debug!(
+            "Attempting to resume connection to {} using {}",
+            self.endpoint.base(),
+            session.transport
+        );
+
+        self.open_transport(self.endpoint.reconnect_url(), session)
+            .await
+    }
+
+    /// Tells the server the connection is finished with, so it doesn't wait for it to time out
+    pub(crate) async fn abort(&self, session: &Session) -> Result<()> {
+        self.transport_request(Method::POST, self.endpoint.abort_url(), session)
+            .timeout(ABORT_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// A request with the parameters and headers every persistent connection endpoint expects
+    pub(crate) fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .version(Version::HTTP_11)
+            .query(&[("clientProtocol", PROTOCOL_VERSION)])
+            .query(&[("connectionData", connection_data())])
+            .header("User", self.user_header.clone())
+            .header(USER_AGENT, USER_AGENT_VALUE)
+    }
+
+    /// A request concerning an established connection, over the transport it's using
+    pub(crate) fn transport_request(
+        &self,
+        method: Method,
+        url: Url,
+        session: &Session,
+    ) -> RequestBuilder {
+        self.request(method, url)
+            .query(&[("transport", session.transport.name())])
+            .query(&[("connectionToken", &session.connection_token)])
+    }
+
+    /// Opens a new connection's transport, waits for the server to initialize it and starts it
+    async fn start_transport(
+        &self,
+        negotiated: &NegotiateResponse,
+        session: &mut Session,
+    ) -> Result<Transport> {
+        if session.transport == TransportKind::WebSockets && !negotiated.try_web_sockets {
+            bail!("Server doesn't accept websocket connections");
+        }
+
+        let mut transport = self
+            .open_transport(self.endpoint.connect_url(), session)
+            .await?;
+
+        let init_timeout = negotiated.transport_connect_timeout.unwrap_or(INIT_TIMEOUT);
+        let init = timeout(init_timeout, wait_for_init(&mut transport.receiver))
+            .await
+            .context("Timed out waiting for the server to initialize the connection")??;
+        session.advance(&init);
+
+        self.start(session).await?;
+
+        Ok(transport)
+    }
+
+    /// Tells the server the transport is ready, after which it starts delivering hub messages
+    async fn start(&self, session: &Session) -> Result<()> {
+        let response = self
+            .transport_request(Method::GET, self.endpoint.start_url(), session)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        match response.get("Response").and_then(Value::as_str) {
+            Some("started") => Ok(()),
+            _ => bail!("Server didn't start the connection: {}", response),
+        }
+    }
+
+    /// Opens the session's transport at `url`, the connect or reconnect endpoint
+    async fn open_transport(&self, url: Url, session: &Session) -> Result<Transport> {
+        match session.transport {
+            TransportKind::WebSockets => {
+                let websocket = self.open_websocket(url, session).await?;
+                Ok(Transport::websocket(websocket))
+            }
+            TransportKind::ServerSentEvents => {
+                let response = self
+                    .cursor_request(Method::GET, url, session)
+                    .header(ACCEPT, "text/event-stream")
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(Transport::server_sent_events(
+                    self.clone(),
+                    session.clone(),
+                    response,
+                ))
+            }
+            TransportKind::LongPolling => {
+                Ok(Transport::long_polling(self.clone(), session.clone(), url))
+            }
+        }
+    }
+
+    /// A transport request which resumes from the last message received, if any
+    fn cursor_request(&self, method: Method, url: Url, session: &Session) -> RequestBuilder {
+        let mut request = self.transport_request(method, url, session);
+        if let Some(message_id) = &session.message_id {
+            request = request.query(&[("messageId", message_id)]);
+        }
+        if let Some(groups_token) = &session.groups_token {
+            request = request.query(&[("groupsToken", groups_token)]);
+        }
+
+        request
+    }
+
+    async fn open_websocket(&self, url: Url, session: &Session) -> Result<Websocket> {
+        let url = self.endpoint.websocket_url(url);
+        let socket_addrs = url.socket_addrs(|| None)?;
+
+        let websocket_request = self
+            .cursor_request(Method::GET, url, session)
+            .header(SEC_WEBSOCKET_KEY, generate_key())
+            .header(SEC_WEBSOCKET_VERSION, 13i32)
+            .header(HOST, self.endpoint.host_header())
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .build()?;
+
+        let request_parts: Parts = http::Request::<Body>::try_from(websocket_request)?
+            .into_parts()
+            .0;
+
+        let stream = TcpStream::connect(&*socket_addrs).await?;
+        let (websocket, _) = tokio_tungstenite::client_async_tls_with_config(
+            Request::from_parts(request_parts, ()),
+            stream,
+            None,
+            Some(Connector::NativeTls(self.tls_connector.clone())),
+        )
+        .await?;
+
+        Ok(websocket)
+    }
+}
+
+/// Reads from a freshly opened transport until the server says the connection is initialized
+async fn wait_for_init(receiver: &mut TransportReceiver) -> Result<ConnectionFrame> {
+    while let Some(inbound) = receiver.next().await {
+        let Inbound::Text(body) = inbound? else {
+            continue;
+        };
+
+        match ServerMessage::parse(&body)? {
+            ServerMessage::Frame(frame) if frame.initialized == Some(1) => return Ok(frame),
+            message => debug!("Ignoring message before initialization: {:?}", message),
+        }
+    }
+
+    bail!("Transport closed before the connection was initialized")
+}
+
+/// Runs a fresh reader and pinger for a newly opened connection until it's lost or a reconnect
+/// is requested, then tears everything belonging to it down again.
+async fn serve(state: &ClientState, transport: Transport, keep_alive: KeepAlive) -> Disconnect {
+    // Stops the writer, and with it anything still being sent, once the connection is done with
+    let cancel = CancellationToken::new();
+    let writer = Writer::spawn(transport.sender, cancel.clone());
+    *state.writer.write().await = Some(writer.clone());
+    set_connection_state(state, ConnectionState::Connected).await;
+    debug!("Connected! Keeping alive with {:?}", keep_alive);
+
+    let disconnect = select! {
+        e = read_messages(state, transport.receiver, keep_alive.timeout) => Disconnect::Lost(e),
+        e = ping(&writer, keep_alive.ping_interval) => Disconnect::Lost(e),
+        never = sync(state) => match never {},
+        never = replay(state) => match never {},
+        _ = state.resume.notified() => Disconnect::ResumeRequested,
+        _ = state.reconnect.notified() => Disconnect::ReconnectRequested,
+    };
+
+    // Abort sends still in progress, then make sure nothing else is sent on the old connection
+    cancel.cancel();
+    *state.writer.write().await = None;
+    // Nothing sent on the old connection will be answered on the next one
+    state.pending_invocations.cancel_all();
+
+    disconnect
+}
+
+/// Lists the queues straight away, as notifications may have been missed while disconnected.
+/// After that the notifications keep them up to date, so they're only listed again when some
+/// look to have been missed, or every so often as a last resort.
+#[tracing::instrument(skip(state), level = "debug")]
+async fn sync(state: &ClientState) -> Infallible {
+    let period = state.settings.read().await.resync_interval();
+    let mut interval = period.map(|period| {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+    // Whatever was missed on the last connection is about to be listed anyway
+    let _ = state.resync.notified().now_or_never();
+
+    loop {
+        list_all_queues(state).await;
+
+        let resync_due = async {
+            match interval.as_mut() {
+                Some(interval) => {
+                    interval.tick().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        select! {
+            _ = resync_due => debug!("Resyncing, as it's been {:?}", period),
+            _ = state.resync.notified() => debug!("Resyncing, as notifications were missed"),
+        }
+    }
+}
+
+/// Sends whatever was issued while disconnected, then anything that joins the outbox behind it,
+/// until the connection goes
+async fn replay(state: &ClientState) -> Infallible {
+    loop {
+        replay_outbox(state).await;
+        state.replay.notified().await;
+    }
+}
+
+/// Pings the server, returning once the connection can't be written to
+#[tracing::instrument(skip(writer), level = "debug")]
+async fn ping(writer: &Writer, ping_interval: Duration) -> anyhow::Error {
+    let mut interval = tokio::time::interval(ping_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    // The first tick completes immediately, and the connection has only just been opened
+    interval.tick().await;
+
+    loop {
+        interval.tick().instrument(debug_span!("Ping timer")).await;
+        if let Err(e) = writer.ping().await.context("Couldn't ping server") {
+            return e;
+        }
+    }
+}
+
+/// Handles messages until the connection closes, fails, or goes quiet for too long
+#[tracing::instrument(skip(state, receiver), level = "debug")]
+async fn read_messages(
+    state: &ClientState,
+    mut receiver: TransportReceiver,
+    keep_alive_timeout: Duration,
+) -> anyhow::Error {
+    loop {
+        match timeout(keep_alive_timeout, receiver.next()).await {
+            Ok(Some(Ok(inbound))) => {
+                state.connection.write().await.last_message_at = Some(Instant::now());
+                let _ = handle_inbound(state, inbound).await.inspect_err(|e| {
+                    warn!("Skipping message, and resyncing: {}", e);
+                    state.resync.notify_one();
+                });
+            }
+            Ok(Some(Err(e))) => return e.context("Transport error"),
+            Ok(None) => return anyhow!("Connection closed by server"),
+            Err(_) => return anyhow!("No messages for {:?}", keep_alive_timeout),
+        }
+    }
+}
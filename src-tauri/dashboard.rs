@@ -0,0 +1,466 @@
+//! `rq-cli dashboard`: a full-screen view of every queue that keeps up with the server, like the
+//! main window of the app does.
+
+use std::collections::BTreeMap;
+use std::io::{self, Stdout};
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use futures_util::StreamExt;
+use rq::{
+    ClientEvent, CommandError, ConnectionState, ConnectionStatus, QHubClient, Queue, QueueChange,
+    QueueStatus,
+};
+use tokio::select;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table, TableState};
+use tui::{Frame, Terminal};
+
+const KEY_HELP: &str = "↑/↓ select  j join  l leave  s start  n nag  m message  q quit";
+
+/// Shows the queues until the user quits, invoking hub methods on the selected queue as keys are
+/// pressed. Unlike the other commands this keeps running while the connection is down.
+pub(crate) async fn run(
+    client: &QHubClient,
+    mut events: broadcast::Receiver<ClientEvent>,
+) -> Result<()> {
+    let mut dashboard = Dashboard {
+        username: client.settings().await.username,
+        connection: client.connection_status().await,
+        queues: client.queues().await,
+        selected: None,
+        mode: Mode::Browsing,
+        notice: None,
+    };
+    dashboard.keep_selection();
+
+    let mut terminal = Screen::enter()?;
+    let mut keys = EventStream::new();
+    // Invocations finish in the background, so the view keeps updating while they're in flight
+    let (notices_tx, mut notices) = mpsc::unbounded_channel();
+
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        select! {
+            event = events.recv() => match event {
+                Ok(event) => dashboard.handle_event(event),
+                Err(RecvError::Lagged(_)) => dashboard.update_queues(client.queues().await),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            key = keys.next() => match key {
+                Some(Ok(Event::Key(key))) => match dashboard.handle_key(key) {
+                    Some(Input::Quit) => return Ok(()),
+                    Some(Input::Invoke(id, action)) => {
+                        let name = dashboard.queues.get(&id).map(|queue| queue.name.clone()).unwrap_or_default();
+                        let client = client.clone();
+                        let notices_tx = notices_tx.clone();
+                        tokio::spawn(async move {
+                            let notice = match action.invoke(&client, id).await {
+                                Ok(()) => format!("{} {}", action.done(), name),
+                                Err(e) => format!("Couldn't {} {}: {:#}", action.verb(), name, e),
+                            };
+                            notices_tx.send(notice).ok();
+                        });
+                    }
+                    None => {}
+                },
+                // Anything else, like a resize, just needs a redraw
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+            Some(notice) = notices.recv() => dashboard.notice = Some(notice),
+        }
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, put back the way it was when dropped
+struct Screen(Terminal<CrosstermBackend<Stdout>>);
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        if let Err(e) = execute!(stdout, EnterAlternateScreen) {
+            disable_raw_mode().ok();
+            return Err(e.into());
+        }
+
+        Ok(Self(Terminal::new(CrosstermBackend::new(stdout))?))
+    }
+}
+
+impl Deref for Screen {
+    type Target = Terminal<CrosstermBackend<Stdout>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Screen {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        disable_raw_mode().ok();
+        execute!(self.0.backend_mut(), LeaveAlternateScreen).ok();
+        self.0.show_cursor().ok();
+    }
+}
+
+enum Mode {
+    Browsing,
+    /// Typing a message to the selected queue
+    Composing(String),
+}
+
+/// What a key press asks for
+enum Input {
+    Quit,
+    Invoke(u64, Action),
+}
+
+/// A hub method that can be invoked on the selected queue
+enum Action {
+    Join,
+    Leave,
+    Start,
+    Nag,
+    Message(String),
+}
+
+impl Action {
+    async fn invoke(&self, client: &QHubClient, id: u64) -> Result<(), CommandError> {
+        match self {
+            Action::Join => client.join_queue(id).await,
+            Action::Leave => client.leave_queue(id).await,
+            Action::Start => client.start_queue(id).await,
+            Action::Nag => client.nag_queue(id).await,
+            Action::Message(text) => client.message_queue(id, text).await,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Action::Join => "join",
+            Action::Leave => "leave",
+            Action::Start => "start",
+            Action::Nag => "nag",
+            Action::Message(_) => "send a message to",
+        }
+    }
+
+    fn done(&self) -> &'static str {
+        match self {
+            Action::Join => "Joined",
+            Action::Leave => "Left",
+            Action::Start => "Started",
+            Action::Nag => "Nagged",
+            Action::Message(_) => "Sent a message to",
+        }
+    }
+}
+
+struct Dashboard {
+    username: String,
+    connection: ConnectionStatus,
+    queues: BTreeMap<u64, Queue>,
+    /// Tracked by ID, so the selection stays put when queues are added in front of it
+    selected: Option<u64>,
+    mode: Mode,
+    /// The outcome of the last invocation, or the last thing the server announced
+    notice: Option<String>,
+}
+
+impl Dashboard {
+    fn handle_event(&mut self, event: ClientEvent) {
+        match event {
+            ClientEvent::ConnectionStatus(status) => self.connection = status,
+            ClientEvent::QueuesListed(queues) => self.update_queues(queues),
+            ClientEvent::QueueChanged { change, queue } => {
+                if let Some(notice) = describe_change(change, &queue) {
+                    self.notice = Some(notice);
+                }
+                self.queues.insert(queue.id, queue);
+                self.keep_selection();
+            }
+            ClientEvent::MessageSent { queue, message } => {
+                self.notice = Some(format!(
+                    "{} in {}: {}",
+                    message.sender.username, queue.name, message.content
+                ));
+                self.queues.insert(queue.id, queue);
+            }
+            // The events above already carry the queues as they are now, and the dashboard's
+            // commands aren't queued while disconnected
+            ClientEvent::QueuesDiffed(_) | ClientEvent::OutboxChanged(_) => {}
+            ClientEvent::Nagged { queue, by } => {
+                let by = by.map(|user| user.username);
+                self.notice = Some(format!(
+                    "{} nagged {}",
+                    by.as_deref().unwrap_or("Someone"),
+                    queue.name
+                ));
+            }
+        }
+    }
+
+    fn update_queues(&mut self, queues: BTreeMap<u64, Queue>) {
+        self.queues = queues;
+        self.keep_selection();
+    }
+
+    /// Selects the first queue if the selected one is gone
+    fn keep_selection(&mut self) {
+        if !matches!(self.selected, Some(id) if self.queues.contains_key(&id)) {
+            self.selected = self.queues.keys().next().copied();
+        }
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected?;
+        self.queues.keys().position(|&id| id == selected)
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        let Some(index) = self.selected_index() else {
+            return;
+        };
+        let last = self.queues.len().saturating_sub(1) as isize;
+        let index = (index as isize + offset).clamp(0, last) as usize;
+        self.selected = self.queues.keys().nth(index).copied();
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Input> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Input::Quit);
+        }
+
+        if let Mode::Composing(text) = &mut self.mode {
+            match key.code {
+                KeyCode::Char(c) => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Esc => self.mode = Mode::Browsing,
+                KeyCode::Enter => {
+                    let text = std::mem::take(text);
+                    self.mode = Mode::Browsing;
+                    if !text.trim().is_empty() {
+                        return Some(Input::Invoke(self.selected?, Action::Message(text)));
+                    }
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        let action = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Input::Quit),
+            KeyCode::Up => {
+                self.move_selection(-1);
+                return None;
+            }
+            KeyCode::Down => {
+                self.move_selection(1);
+                return None;
+            }
+            KeyCode::Char('m') => {
+                if self.selected.is_some() {
+                    self.mode = Mode::Composing(String::new());
+                }
+                return None;
+            }
+            KeyCode::Char('j') => Action::Join,
+            KeyCode::Char('l') => Action::Leave,
+            KeyCode::Char('s') => Action::Start,
+            KeyCode::Char('n') => Action::Nag,
+            _ => return None,
+        };
+
+        Some(Input::Invoke(self.selected?, action))
+    }
+
+    fn draw<B: Backend>(&self, frame: &mut Frame<B>) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(2)])
+            .split(frame.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+            .split(rows[0]);
+
+        self.draw_queues(frame, columns[0]);
+        if let Some(queue) = self.selected.and_then(|id| self.queues.get(&id)) {
+            self.draw_queue(frame, columns[1], queue);
+        } else {
+            frame.render_widget(Block::default().borders(Borders::ALL), columns[1]);
+        }
+        self.draw_footer(frame, rows[1]);
+    }
+
+    fn draw_queues<B: Backend>(&self, frame: &mut Frame<B>, area: Rect) {
+        let rows = self.queues.values().map(|queue| {
+            let member = if self.is_member(queue) { "*" } else { "" };
+            Row::new(vec![
+                Cell::from(member),
+                Cell::from(queue.name.clone()),
+                Cell::from(format!("{:?}", queue.status)).style(status_style(queue.status)),
+                Cell::from(queue.members.len().to_string()),
+            ])
+        });
+
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["", "Queue", "Status", "Members"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Queues"))
+            .widths(&[
+                Constraint::Length(1),
+                Constraint::Percentage(55),
+                Constraint::Length(8),
+                Constraint::Length(7),
+            ])
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        let mut state = TableState::default();
+        state.select(self.selected_index());
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+
+    fn draw_queue<B: Backend>(&self, frame: &mut Frame<B>, area: Rect, queue: &Queue) {
+        let parts = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(queue.members.len().max(1) as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .split(area);
+
+        let members: Vec<ListItem> = if queue.members.is_empty() {
+            vec![ListItem::new(Span::styled(
+                "Nobody yet",
+                Style::default().fg(Color::DarkGray),
+            ))]
+        } else {
+            queue
+                .members
+                .iter()
+                .map(|member| ListItem::new(format!("{} ({})", member.full_name, member.username)))
+                .collect()
+        };
+        let mut title = queue.name.clone();
+        if !queue.restrict_to_group.is_empty() {
+            title = format!("{} [{}]", title, queue.restrict_to_group);
+        }
+        frame.render_widget(
+            List::new(members).block(Block::default().borders(Borders::ALL).title(title)),
+            parts[0],
+        );
+
+        // Only the latest messages that fit, oldest at the top
+        let fits = parts[1].height.saturating_sub(2) as usize;
+        let messages: Vec<ListItem> = queue
+            .messages
+            .iter()
+            .skip(queue.messages.len().saturating_sub(fits))
+            .map(|message| {
+                ListItem::new(Spans::from(vec![
+                    Span::styled(
+                        format!("{}: ", message.sender.username),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(message.content.clone()),
+                ]))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(messages).block(Block::default().borders(Borders::ALL).title("Messages")),
+            parts[1],
+        );
+    }
+
+    fn draw_footer<B: Backend>(&self, frame: &mut Frame<B>, area: Rect) {
+        let first = match &self.mode {
+            Mode::Composing(text) => {
+                let name = self
+                    .selected
+                    .and_then(|id| self.queues.get(&id))
+                    .map(|queue| queue.name.as_str())
+                    .unwrap_or_default();
+                Spans::from(format!("Message to {}: {}█", name, text))
+            }
+            Mode::Browsing => {
+                let mut spans = vec![Span::styled(
+                    self.connection.summary(),
+                    Style::default().fg(connection_color(&self.connection)),
+                )];
+                if let Some(notice) = &self.notice {
+                    spans.push(Span::raw(format!("  {}", notice)));
+                }
+                Spans::from(spans)
+            }
+        };
+        let help = match self.mode {
+            Mode::Composing(_) => "enter send  esc cancel",
+            Mode::Browsing => KEY_HELP,
+        };
+
+        frame.render_widget(
+            Paragraph::new(vec![
+                first,
+                Spans::from(Span::styled(help, Style::default().fg(Color::DarkGray))),
+            ]),
+            area,
+        );
+    }
+
+    fn is_member(&self, queue: &Queue) -> bool {
+        queue
+            .members
+            .iter()
+            .any(|member| member.username == self.username)
+    }
+}
+
+fn status_style(status: QueueStatus) -> Style {
+    Style::default().fg(match status {
+        QueueStatus::Open => Color::Green,
+        QueueStatus::Started => Color::Yellow,
+        QueueStatus::Closed => Color::DarkGray,
+    })
+}
+
+fn connection_color(status: &ConnectionStatus) -> Color {
+    match status.state {
+        ConnectionState::Connected => Color::Green,
+        ConnectionState::Disconnected => Color::Red,
+        _ => Color::Yellow,
+    }
+}
+
+/// The notices for the same changes that bring the app's window to the front
+fn describe_change(change: QueueChange, queue: &Queue) -> Option<String> {
+    match change {
+        QueueChange::Created => Some(format!("{} was created", queue.name)),
+        QueueChange::StatusChanged => Some(format!("{} is now {:?}", queue.name, queue.status)),
+        QueueChange::MembershipChanged => None,
+    }
+}
@@ -0,0 +1,134 @@
+//! Working out what changed between two versions of the queues, so frontends can react to just
+//! that instead of redrawing everything.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::queue::{Message, Queue, QueueStatus, User};
+
+/// A single change to the queues
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum QueueDiff {
+    Added {
+        queue: Queue,
+    },
+    Removed {
+        queue: Queue,
+    },
+    StatusChanged {
+        id: u64,
+        from: QueueStatus,
+        to: QueueStatus,
+    },
+    MemberJoined {
+        id: u64,
+        user: User,
+    },
+    MemberLeft {
+        id: u64,
+        user: User,
+    },
+    MessageAppended {
+        id: u64,
+        message: Message,
+    },
+    /// The queue changed in some other way, like being renamed or having its messages cleared
+    Replaced {
+        queue: Queue,
+    },
+}
+
+impl QueueDiff {
+    /// The ID of the queue that changed
+    pub fn id(&self) -> u64 {
+        match self {
+            QueueDiff::Added { queue }
+            | QueueDiff::Removed { queue }
+            | QueueDiff::Replaced { queue } => queue.id,
+            QueueDiff::StatusChanged { id, .. }
+            | QueueDiff::MemberJoined { id, .. }
+            | QueueDiff::MemberLeft { id, .. }
+            | QueueDiff::MessageAppended { id, .. } => *id,
+        }
+    }
+}
+
+/// Every change from one list of queues to the next
+pub(crate) fn diff_queues(
+    old: &BTreeMap<u64, Queue>,
+    new: &BTreeMap<u64, Queue>,
+) -> Vec<QueueDiff> {
+    let removed = old
+        .values()
+        .filter(|queue| !new.contains_key(&queue.id))
+        .map(|queue| QueueDiff::Removed {
+            queue: queue.clone(),
+        });
+    let changed = new
+        .values()
+        .flat_map(|queue| diff_queue(old.get(&queue.id), queue));
+
+    removed.chain(changed).collect()
+}
+
+/// Every change to one queue, which is new if there's no old version of it
+pub(crate) fn diff_queue(old: Option<&Queue>, new: &Queue) -> Vec<QueueDiff> {
+    let Some(old) = old else {
+        return vec![QueueDiff::Added { queue: new.clone() }];
+    };
+    let id = new.id;
+
+    // Members are in the order they joined, so whoever is still there should be followed by
+    // whoever just joined
+    let stayed: Vec<User> = old
+        .members
+        .iter()
+        .filter(|member| new.members.contains(member))
+        .cloned()
+        .collect();
+
+    // Anything the finer-grained diffs can't describe means starting over with the whole queue
+    if old.name != new.name
+        || old.restrict_to_group != new.restrict_to_group
+        || !new.members.starts_with(&stayed)
+        || !new.messages.starts_with(&old.messages)
+    {
+        return vec![QueueDiff::Replaced { queue: new.clone() }];
+    }
+
+    let mut diffs = Vec::new();
+    if old.status != new.status {
+        diffs.push(QueueDiff::StatusChanged {
+            id,
+            from: old.status,
+            to: new.status,
+        });
+    }
+    diffs.extend(
+        old.members
+            .iter()
+            .filter(|member| !new.members.contains(member))
+            .map(|user| QueueDiff::MemberLeft {
+                id,
+                user: user.clone(),
+            }),
+    );
+    diffs.extend(
+        new.members[stayed.len()..]
+            .iter()
+            .map(|user| QueueDiff::MemberJoined {
+                id,
+                user: user.clone(),
+            }),
+    );
+    diffs.extend(new.messages[old.messages.len()..].iter().map(|message| {
+        QueueDiff::MessageAppended {
+            id,
+            message: message.clone(),
+        }
+    }));
+
+    diffs
+}
@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+
+pub(crate) const DEFAULT_SERVER_URL: &str = "http://poolq3.zoo.lan/signalr";
+
+/// The SignalR endpoint of a QHub server, derived from the `server_url` setting.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ServerEndpoint {
+    base: Url,
+}
+
+impl ServerEndpoint {
+    pub(crate) fn parse(server_url: &str) -> Result<Self> {
+        let mut base = Url::parse(server_url.trim())
+            .with_context(|| format!("{:?} is not a valid URL", server_url))?;
+
+        match base.scheme() {
+            "http" | "https" => {}
+            scheme => bail!("Unsupported scheme {:?}, expected http or https", scheme),
+        }
+
+        if base.host_str().is_none() {
+            bail!("Server URL {:?} has no host", server_url);
+        }
+
+        if base.query().is_some() || base.fragment().is_some() {
+            bail!(
+                "Server URL {:?} must not contain a query or fragment",
+                server_url
+            );
+        }
+
+        // Make sure `join` appends to the path prefix rather than replacing its last segment
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+
+        Ok(Self { base })
+    }
+
+    pub(crate) fn base(&self) -> &Url {
+        &self.base
+    }
+
+    pub(crate) fn negotiate_url(&self) -> Url {
+        self.base
+            .join("negotiate")
+            .expect("Couldn't derive negotiate url")
+    }
+
+    pub(crate) fn connect_url(&self) -> Url {
+        self.base
+            .join("connect")
+            .expect("Couldn't derive connect url")
+    }
+
+    /// Resumes a dropped connection, as opposed to [`Self::connect_url`] which opens a new one
+    pub(crate) fn reconnect_url(&self) -> Url {
+        self.base
+            .join("reconnect")
+            .expect("Couldn't derive reconnect url")
+    }
+
+    pub(crate) fn start_url(&self) -> Url {
+        self.base.join("start").expect("Couldn't derive start url")
+    }
+
+    pub(crate) fn abort_url(&self) -> Url {
+        self.base.join("abort").expect("Couldn't derive abort url")
+    }
+
+    /// Where messages are sent by transports that can't send them over the connection itself
+    pub(crate) fn send_url(&self) -> Url {
+        self.base.join("send").expect("Couldn't derive send url")
+    }
+
+    pub(crate) fn poll_url(&self) -> Url {
+        self.base.join("poll").expect("Couldn't derive poll url")
+    }
+
+    pub(crate) fn ping_url(&self) -> Url {
+        self.base.join("ping").expect("Couldn't derive ping url")
+    }
+
+    /// The same url with the matching websocket scheme, for opening a websocket transport
+    pub(crate) fn websocket_url(&self, mut url: Url) -> Url {
+        let scheme = if self.is_secure() { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .expect("Couldn't set websocket scheme on url");
+        url
+    }
+
+    pub(crate) fn is_secure(&self) -> bool {
+        self.base.scheme() == "https"
+    }
+
+    /// Value of the `Host` header for the websocket upgrade request
+    pub(crate) fn host_header(&self) -> String {
+        let host = self.base.host_str().unwrap_or_default();
+        match self.base.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
+        }
+    }
+}
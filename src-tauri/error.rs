@@ -0,0 +1,100 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::invocations::InvocationError;
+
+/// Anything that can go wrong while handling a message from the server. None of these are fatal:
+/// the offending frame is logged and skipped, and the reader carries on with the next one.
+#[derive(Debug, Error)]
+pub(crate) enum InboundError {
+    #[error("Message is not valid JSON: {0}")]
+    InvalidJson(#[source] serde_json::Error),
+
+    #[error("Expected a JSON object, got {0}")]
+    NotAnObject(String),
+
+    #[error("Malformed {what}: {source}")]
+    Malformed {
+        what: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("{method} notification is missing its arguments")]
+    MissingArguments { method: String },
+
+    #[error("Notification for queue {0}, which isn't listed")]
+    UnknownQueue(u64),
+
+    #[error("Queue {id} has unknown status {status}")]
+    UnknownQueueStatus { id: u64, status: u64 },
+
+    #[error("Unexpected binary frame of {0} bytes")]
+    UnexpectedBinary(usize),
+}
+
+/// Why a command couldn't be carried out, in a form frontends can tell apart
+#[derive(Debug, Error, Serialize, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CommandError {
+    #[error("Not connected to the server")]
+    NotConnected,
+
+    #[error("Timed out waiting for the server to respond")]
+    Timeout,
+
+    /// The server refused the command
+    #[error("Hub error: {message}")]
+    HubError { message: String },
+
+    /// The settings couldn't be loaded or stored
+    #[error("{message}")]
+    Settings { message: String },
+
+    /// The connection was reset before the server responded
+    #[error("Connection was reset before the server responded")]
+    Cancelled,
+
+    #[error("{message}")]
+    InvalidInput { message: String },
+
+    /// The history isn't being kept, or couldn't be read
+    #[error("{message}")]
+    History { message: String },
+
+    /// The window couldn't be sent what it asked for
+    #[error("{message}")]
+    Ui { message: String },
+}
+
+impl CommandError {
+    pub fn settings(error: anyhow::Error) -> Self {
+        Self::Settings {
+            message: format!("{:#}", error),
+        }
+    }
+
+    pub fn invalid_input(error: anyhow::Error) -> Self {
+        Self::InvalidInput {
+            message: format!("{:#}", error),
+        }
+    }
+
+    pub fn ui(error: anyhow::Error) -> Self {
+        Self::Ui {
+            message: format!("{:#}", error),
+        }
+    }
+}
+
+impl From<InvocationError> for CommandError {
+    fn from(error: InvocationError) -> Self {
+        match error {
+            InvocationError::Timeout => Self::Timeout,
+            InvocationError::Disconnected => Self::Cancelled,
+            InvocationError::Hub(error) => Self::HubError {
+                message: error.message,
+            },
+        }
+    }
+}
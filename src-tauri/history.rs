@@ -0,0 +1,292 @@
+//! A local log of everything that happened to the queues, kept after the queues themselves are
+//! gone. Entries are appended to a file as JSON lines, oldest first.
+
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::log::warn;
+
+use crate::client::{ClientEvent, QueueChange};
+use crate::diff::QueueDiff;
+use crate::queue::{Queue, QueueStatus};
+
+/// Something that happened to a queue
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+    pub queue_id: u64,
+    /// The queue's name at the time
+    pub queue_name: String,
+    /// The username of whoever it happened to or did it, if known
+    pub user: Option<String>,
+    pub event: HistoryEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum HistoryEvent {
+    Created,
+    Started,
+    Reset,
+    Closed,
+    MemberJoined,
+    MemberLeft,
+    Message { content: String },
+    Nagged,
+}
+
+/// Which entries to look up. Conditions that aren't set match every entry.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryQuery {
+    pub queue_id: Option<u64>,
+    pub user: Option<String>,
+    /// Milliseconds since the Unix epoch, inclusive
+    pub since: Option<u64>,
+    /// Milliseconds since the Unix epoch, inclusive
+    pub until: Option<u64>,
+    /// At most this many entries, the latest ones
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        if matches!(self.queue_id, Some(id) if id != entry.queue_id) {
+            return false;
+        }
+        if let Some(user) = &self.user {
+            if entry.user.as_ref() != Some(user) {
+                return false;
+            }
+        }
+        if matches!(self.since, Some(since) if entry.at < since) {
+            return false;
+        }
+        if matches!(self.until, Some(until) if entry.at > until) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// How much of the log is read at a time when looking entries up
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl History {
+    /// Opens the log at `path`, creating it and its directory if needed
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("Couldn't create {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Couldn't open history at {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Logs whatever an event says happened to the queues. Most events only carry queue IDs, so
+    /// the names are looked up in `queues`.
+    pub async fn record(&self, event: &ClientEvent, queues: &BTreeMap<u64, Queue>) -> Result<()> {
+        let entries = entries_for(event, queues, now());
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+
+        // One write per event, so entries can't end up interleaved
+        let mut file = self.file.lock().await;
+        file.write_all(lines.as_bytes())
+            .await
+            .context("Couldn't write to history")?;
+        // Otherwise the write may not have happened yet when the history is next looked up
+        file.flush().await.context("Couldn't write to history")
+    }
+
+    /// Every entry matching the query, oldest first. The log is read backwards from the end, and
+    /// only as far back as `since` and `limit` need.
+    pub async fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        let mut file = File::open(&self.path)
+            .await
+            .with_context(|| format!("Couldn't open history at {}", self.path.display()))?;
+        let mut pos = file
+            .metadata()
+            .await
+            .context("Couldn't read history")?
+            .len();
+
+        let mut entries = Vec::new();
+        // The start of the chunk read last, which is part of a line that began further back
+        let mut rest = Vec::new();
+        while pos > 0 && query.limit != Some(entries.len()) {
+            let len = pos.min(CHUNK_SIZE);
+            pos -= len;
+
+            let mut chunk = vec![0; len as usize];
+            file.seek(SeekFrom::Start(pos))
+                .await
+                .context("Couldn't read history")?;
+            file.read_exact(&mut chunk)
+                .await
+                .context("Couldn't read history")?;
+            chunk.append(&mut rest);
+
+            let mut lines = chunk.split(|&byte| byte == b'\n');
+            // The first line may have begun in the part of the log that hasn't been read yet
+            let first = lines.next().unwrap_or_default();
+            let complete = lines.rev().chain((pos == 0).then_some(first));
+            if !collect_entries(complete, query, &mut entries) {
+                break;
+            }
+            rest = first.to_vec();
+        }
+
+        entries.reverse();
+        Ok(entries)
+    }
+}
+
+/// Adds the entries in `lines`, latest first, that match the query to `entries`. Returns whether
+/// earlier lines could match too.
+fn collect_entries<'a>(
+    lines: impl Iterator<Item = &'a [u8]>,
+    query: &HistoryQuery,
+    entries: &mut Vec<HistoryEntry>,
+) -> bool {
+    for line in lines.filter(|line| !line.is_empty()) {
+        // A line cut short by a crash shouldn't hide the rest of the history
+        let Ok(entry) = serde_json::from_slice::<HistoryEntry>(line)
+            .inspect_err(|e| warn!("Skipping history entry: {}", e))
+        else {
+            continue;
+        };
+        // Entries are appended as they happen, so everything before this is older still
+        if matches!(query.since, Some(since) if entry.at < since) {
+            return false;
+        }
+        if query.matches(&entry) {
+            entries.push(entry);
+            if query.limit == Some(entries.len()) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// New queues and nags come from their own events, everything else from the diffs, which also
+/// catch changes that were only noticed by listing the queues again
+fn entries_for(event: &ClientEvent, queues: &BTreeMap<u64, Queue>, at: u64) -> Vec<HistoryEntry> {
+    let entry = |queue: &Queue, user: Option<&str>, event| HistoryEntry {
+        at,
+        queue_id: queue.id,
+        queue_name: queue.name.clone(),
+        user: user.map(ToOwned::to_owned),
+        event,
+    };
+
+    match event {
+        ClientEvent::QueueChanged {
+            change: QueueChange::Created,
+            queue,
+        } => {
+            // Whoever creates a queue is its first member
+            let creator = queue.members.first().map(|user| user.username.as_str());
+            vec![entry(queue, creator, HistoryEvent::Created)]
+        }
+        ClientEvent::Nagged { queue, by } => {
+            let by = by.as_ref().map(|user| user.username.as_str());
+            vec![entry(queue, by, HistoryEvent::Nagged)]
+        }
+        ClientEvent::QueuesDiffed(diffs) => diffs
+            .iter()
+            .filter_map(|diff| entry_for(diff, queues, at))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn entry_for(diff: &QueueDiff, queues: &BTreeMap<u64, Queue>, at: u64) -> Option<HistoryEntry> {
+    let entry = |queue_id, user: Option<&str>, event| HistoryEntry {
+        at,
+        queue_id,
+        queue_name: queues
+            .get(&queue_id)
+            .map(|queue| queue.name.clone())
+            .unwrap_or_default(),
+        user: user.map(ToOwned::to_owned),
+        event,
+    };
+
+    let entry = match diff {
+        // Either just created, which has its own event, or listed for the first time
+        QueueDiff::Added { .. } | QueueDiff::Replaced { .. } => return None,
+        // Closed queues are usually announced before they disappear
+        QueueDiff::Removed { queue } if queue.status == QueueStatus::Closed => return None,
+        QueueDiff::Removed { queue } => HistoryEntry {
+            queue_name: queue.name.clone(),
+            ..entry(queue.id, None, HistoryEvent::Closed)
+        },
+        QueueDiff::StatusChanged { id, to, .. } => entry(
+            *id,
+            None,
+            match to {
+                QueueStatus::Open => HistoryEvent::Reset,
+                QueueStatus::Started => HistoryEvent::Started,
+                QueueStatus::Closed => HistoryEvent::Closed,
+            },
+        ),
+        QueueDiff::MemberJoined { id, user } => {
+            entry(*id, Some(&user.username), HistoryEvent::MemberJoined)
+        }
+        QueueDiff::MemberLeft { id, user } => {
+            entry(*id, Some(&user.username), HistoryEvent::MemberLeft)
+        }
+        QueueDiff::MessageAppended { id, message } => entry(
+            *id,
+            Some(&message.sender.username),
+            HistoryEvent::Message {
+                content: message.content.clone(),
+            },
+        ),
+    };
+
+    Some(entry)
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use crate::protocol::{HubError, HubResult};
+
+/// How long to wait for the server to answer an invocation before giving up
+pub(crate) const INVOCATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+type InvocationResult = Result<Value, HubError>;
+
+#[derive(Debug)]
+pub(crate) enum InvocationError {
+    /// The server didn't answer within [`INVOCATION_TIMEOUT`]
+    Timeout,
+    /// The connection was replaced or dropped before the server answered
+    Disconnected,
+    Hub(HubError),
+}
+
+impl fmt::Display for InvocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "Timed out waiting for the server to respond"),
+            Self::Disconnected => write!(f, "Disconnected before the server responded"),
+            Self::Hub(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for InvocationError {}
+
+/// Hub invocations that have been sent but not yet answered, keyed by invocation id
+#[derive(Debug, Default)]
+pub(crate) struct PendingInvocations {
+    senders: Mutex<HashMap<u64, oneshot::Sender<InvocationResult>>>,
+}
+
+impl PendingInvocations {
+    /// Must be called before the invocation is sent, so a fast reply can't arrive unclaimed
+    pub(crate) fn register(&self, id: u64) -> PendingInvocation<'_> {
+        let (sender, receiver) = oneshot::channel();
+        self.senders
+            .lock()
+            .expect("Pending invocations lock poisoned")
+            .insert(id, sender);
+
+        PendingInvocation {
+            id,
+            receiver,
+            pending: self,
+        }
+    }
+
+    /// Hands a result to whoever is waiting on it. Returns false if nobody was.
+    pub(crate) fn resolve(&self, result: HubResult) -> bool {
+        let sender = self
+            .senders
+            .lock()
+            .expect("Pending invocations lock poisoned")
+            .remove(&result.id);
+
+        match sender {
+            Some(sender) => sender.send(result.into_result()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Fails every outstanding invocation, for when the connection they were sent on is gone
+    pub(crate) fn cancel_all(&self) {
+        self.senders
+            .lock()
+            .expect("Pending invocations lock poisoned")
+            .clear();
+    }
+
+    fn remove(&self, id: u64) {
+        self.senders
+            .lock()
+            .expect("Pending invocations lock poisoned")
+            .remove(&id);
+    }
+}
+
+/// Receiving end of a registered invocation. Dropping it unregisters the invocation.
+pub(crate) struct PendingInvocation<'a> {
+    id: u64,
+    receiver: oneshot::Receiver<InvocationResult>,
+    pending: &'a PendingInvocations,
+}
+
+impl PendingInvocation<'_> {
+    pub(crate) async fn wait(mut self) -> Result<Value, InvocationError> {
+        match timeout(INVOCATION_TIMEOUT, &mut self.receiver).await {
+            Ok(Ok(result)) => result.map_err(InvocationError::Hub),
+            Ok(Err(_)) => Err(InvocationError::Disconnected),
+            Err(_) => Err(InvocationError::Timeout),
+        }
+    }
+}
+
+impl Drop for PendingInvocation<'_> {
+    fn drop(&mut self) {
+        self.pending.remove(self.id);
+    }
+}
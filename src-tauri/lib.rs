@@ -0,0 +1,35 @@
+//! A client for QHub, the queue server rq talks to, usable without the desktop app.
+//!
+//! [`QHubClient`] keeps a connection to the server in the background, tracks every queue it
+//! hears about and invokes hub methods on them. Frontends subscribe to its [`ClientEvent`]s to
+//! find out when anything changes.
+
+#![feature(result_option_inspect)]
+
+mod cache;
+mod client;
+mod connection;
+mod diff;
+mod endpoint;
+mod error;
+mod history;
+mod invocations;
+mod outbox;
+mod protocol;
+mod queue;
+mod rules;
+mod settings;
+mod tls;
+mod transport;
+mod writer;
+
+pub use client::{ClientEvent, QHubClient, QueueChange};
+pub use connection::{ConnectionState, ConnectionStatus};
+pub use diff::QueueDiff;
+pub use error::CommandError;
+pub use history::{History, HistoryEntry, HistoryEvent, HistoryQuery};
+pub use outbox::{OutboxCommand, PendingCommand};
+pub use queue::{Message, Queue, QueueStatus, User};
+pub use rules::{EventKind, NotificationAction, NotificationRule};
+pub use settings::{Notifications, Settings, Theme};
+pub use transport::TransportKind;
@@ -5,6 +5,8 @@ use tauri_egui::{
     EguiPluginHandle,
 };
 
+use tracing::log::warn;
+
 use crate::commands::new_queue;
 
 pub struct NewQueueApp {
@@ -39,7 +41,7 @@ impl eframe::App for NewQueueApp {
                 frame.close();
                 tauri::async_runtime::spawn(async move {
                     let app = app.clone();
-                    new_queue(
+                    let _ = new_queue(
                         app.state(),
                         &name,
                         if restrict_to_group.is_empty() {
@@ -49,7 +51,7 @@ impl eframe::App for NewQueueApp {
                         },
                     )
                     .await
-                    .unwrap();
+                    .inspect_err(|e| warn!("Couldn't create queue: {}", e));
                 });
             }
         });
@@ -0,0 +1,106 @@
+//! Alerts the user to the queue events they want to hear about, as their notification rules say.
+
+use anyhow::{anyhow, Result};
+use rq::{ClientEvent, NotificationAction, QHubClient, QueueChange, QueueStatus, User};
+use tauri::api::notification::Notification;
+use tauri::{AppHandle, Manager};
+
+/// Tells the user what happened and who did it, in whichever ways their settings ask for
+pub(crate) async fn notify(app: &AppHandle, event: &ClientEvent) -> Result<()> {
+    let actions = app
+        .state::<QHubClient>()
+        .settings()
+        .await
+        .notification_actions(event);
+
+    for action in actions {
+        match action {
+            NotificationAction::Popup => show_main_window(app)?,
+            NotificationAction::Notification => {
+                if let Some((title, body)) = describe(event) {
+                    Notification::new(&app.config().tauri.bundle.identifier)
+                        .title(title)
+                        .body(body)
+                        .show()?;
+                }
+            }
+            // The webview does the playing, since it already has the means to
+            NotificationAction::Sound => app.emit_all("play_sound", ())?,
+        }
+    }
+
+    Ok(())
+}
+
+fn show_main_window(app: &AppHandle) -> Result<()> {
+    app.get_window("main")
+        .ok_or_else(|| anyhow!("Couldn't find the main window"))?
+        .show()?;
+
+    Ok(())
+}
+
+fn describe(event: &ClientEvent) -> Option<(String, String)> {
+    let described = match event {
+        // Whoever creates a queue is its first member
+        ClientEvent::QueueChanged {
+            change: QueueChange::Created,
+            queue,
+        } => (
+            "New queue".to_owned(),
+            match queue.members.first() {
+                Some(creator) => format!("{} created {}", display_name(creator), queue.name),
+                None => format!("{} was created", queue.name),
+            },
+        ),
+        ClientEvent::QueueChanged {
+            change: QueueChange::StatusChanged,
+            queue,
+        } => {
+            let body = match queue.status {
+                QueueStatus::Open => "Reset, and open to join again",
+                QueueStatus::Started => "Started, it's time to go",
+                QueueStatus::Closed => "Closed",
+            };
+            (queue.name.clone(), body.to_owned())
+        }
+        ClientEvent::QueueChanged {
+            change: QueueChange::MembershipChanged,
+            queue,
+        } => {
+            let body = if queue.members.is_empty() {
+                "Everyone has left".to_owned()
+            } else {
+                let members: Vec<_> = queue.members.iter().map(display_name).collect();
+                format!("Now {}", members.join(", "))
+            };
+            (queue.name.clone(), body)
+        }
+        ClientEvent::Nagged { queue, by } => {
+            let body = match by {
+                Some(user) => format!(
+                    "{} is waiting on everyone in {}",
+                    display_name(user),
+                    queue.name
+                ),
+                None => format!("Everyone in {} is being waited on", queue.name),
+            };
+            (format!("{} is waiting", queue.name), body)
+        }
+        ClientEvent::MessageSent { queue, message } => (
+            format!("{} in {}", display_name(&message.sender), queue.name),
+            message.content.clone(),
+        ),
+        _ => return None,
+    };
+
+    Some(described)
+}
+
+fn display_name(user: &User) -> &str {
+    if user.full_name.is_empty() {
+        &user.username
+    } else {
+        &user.full_name
+    }
+}
@@ -0,0 +1,236 @@
+//! Commands issued while disconnected, kept until the connection is back and then sent in the
+//! order they were issued. The outbox can be kept on disk, so closing the app doesn't lose them.
+
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tracing::log::warn;
+
+use crate::cache::replace_file;
+
+/// A hub method the client can run, either straight away or once it has waited in the outbox
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum OutboxCommand {
+    JoinQueue {
+        id: u64,
+    },
+    LeaveQueue {
+        id: u64,
+    },
+    MessageQueue {
+        id: u64,
+        content: String,
+    },
+    StartQueue {
+        id: u64,
+    },
+    ResetQueue {
+        id: u64,
+    },
+    NagQueue {
+        id: u64,
+    },
+    DeleteQueue {
+        id: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    NewQueue {
+        name: String,
+        restrict_to_group: Option<String>,
+    },
+}
+
+impl OutboxCommand {
+    /// The queue the command is for, unless it creates one
+    pub fn queue_id(&self) -> Option<u64> {
+        match self {
+            Self::JoinQueue { id }
+            | Self::LeaveQueue { id }
+            | Self::MessageQueue { id, .. }
+            | Self::StartQueue { id }
+            | Self::ResetQueue { id }
+            | Self::NagQueue { id }
+            | Self::DeleteQueue { id } => Some(*id),
+            Self::NewQueue { .. } => None,
+        }
+    }
+
+    /// The hub method to invoke and its arguments. The only place commands are mapped to methods.
+    pub(crate) fn invocation(&self) -> (&'static str, Value) {
+        match self {
+            Self::JoinQueue { id } => ("JoinQueue", json!([id])),
+            Self::LeaveQueue { id } => ("LeaveQueue", json!([id])),
+            Self::MessageQueue { id, content } => ("MessageQueue", json!([id, content])),
+            Self::StartQueue { id } => ("ActivateQueue", json!([id])),
+            Self::ResetQueue { id } => ("DeactivateQueue", json!([id])),
+            Self::NagQueue { id } => ("NagQueue", json!([id])),
+            Self::DeleteQueue { id } => ("CloseQueue", json!([id])),
+            Self::NewQueue {
+                name,
+                restrict_to_group,
+            } => (
+                "StartQueue",
+                json!([name, restrict_to_group.as_deref().unwrap_or("")]),
+            ),
+        }
+    }
+
+    /// Whether this undoes `earlier`, so neither needs sending
+    fn undoes(&self, earlier: &OutboxCommand) -> bool {
+        match (earlier, self) {
+            (Self::JoinQueue { id: joined }, Self::LeaveQueue { id: left })
+            | (Self::LeaveQueue { id: left }, Self::JoinQueue { id: joined }) => joined == left,
+            _ => false,
+        }
+    }
+}
+
+/// A command waiting in the outbox
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingCommand {
+    /// Unique within the outbox, oldest first
+    pub id: u64,
+    pub command: OutboxCommand,
+}
+
+#[derive(Debug, Default)]
+struct Waiting {
+    commands: Vec<PendingCommand>,
+    /// The command being replayed, which is too late to cancel out or drop
+    sending: Option<u64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    /// Where the outbox is kept, if anywhere
+    path: Option<PathBuf>,
+    /// Held while saving too, so an older outbox can't overwrite a newer one
+    waiting: Mutex<Waiting>,
+}
+
+impl Outbox {
+    /// An outbox kept at `path`, holding whatever was left in it last time
+    pub(crate) fn new(path: Option<PathBuf>) -> Self {
+        let commands = path
+            .as_ref()
+            .and_then(|path| {
+                load(path)
+                    .inspect_err(|e| warn!("{:#}, starting with an empty outbox", e))
+                    .ok()
+            })
+            .unwrap_or_default();
+
+        Self {
+            path,
+            waiting: Mutex::new(Waiting {
+                commands,
+                sending: None,
+            }),
+        }
+    }
+
+    pub(crate) async fn commands(&self) -> Vec<PendingCommand> {
+        self.waiting.lock().await.commands.clone()
+    }
+
+    /// The oldest command, which is being sent from now on, or `None` once the outbox is empty
+    pub(crate) async fn start_sending(&self) -> Option<PendingCommand> {
+        let mut waiting = self.waiting.lock().await;
+        let first = waiting.commands.first().cloned();
+        waiting.sending = first.as_ref().map(|pending| pending.id);
+        first
+    }
+
+    /// Gives up sending for now, leaving the command for next time
+    pub(crate) async fn stop_sending(&self) {
+        self.waiting.lock().await.sending = None;
+    }
+
+    /// Adds a command, unless it can be sent straight away because nothing else is waiting and
+    /// `connected` says there's a connection to send it over. Decided under the same lock the
+    /// outbox is emptied under, so a command can never overtake one issued before it.
+    ///
+    /// Returns what's in the outbox afterwards, or `None` if the command should be sent now.
+    pub(crate) async fn push_unless_sendable(
+        &self,
+        command: &OutboxCommand,
+        connected: impl Future<Output = bool>,
+    ) -> Option<Vec<PendingCommand>> {
+        let mut waiting = self.waiting.lock().await;
+        if waiting.commands.is_empty() && connected.await {
+            return None;
+        }
+
+        let Waiting { commands, sending } = &mut *waiting;
+        let replaceable = |pending: &PendingCommand| Some(pending.id) != *sending;
+        let latest = commands.iter().rposition(|pending| {
+            replaceable(pending) && pending.command.queue_id() == command.queue_id()
+        });
+        match latest {
+            Some(latest) if command.undoes(&commands[latest].command) => {
+                commands.remove(latest);
+            }
+            // Already waiting to be sent, though the same message can be sent twice
+            Some(latest)
+                if commands[latest].command == *command
+                    && !matches!(command, OutboxCommand::MessageQueue { .. }) => {}
+            _ => {
+                // Deleting a queue makes anything else waiting for it pointless
+                if let OutboxCommand::DeleteQueue { id } = *command {
+                    commands.retain(|pending| {
+                        !replaceable(pending) || pending.command.queue_id() != Some(id)
+                    });
+                }
+                let id = commands.last().map_or(0, |last| last.id + 1);
+                commands.push(PendingCommand {
+                    id,
+                    command: command.clone(),
+                });
+            }
+        }
+
+        self.save(commands).await;
+        Some(commands.clone())
+    }
+
+    /// Takes a command out once it's been sent. Returns what's left in the outbox.
+    pub(crate) async fn remove(&self, id: u64) -> Vec<PendingCommand> {
+        let mut waiting = self.waiting.lock().await;
+        waiting.commands.retain(|pending| pending.id != id);
+        if waiting.sending == Some(id) {
+            waiting.sending = None;
+        }
+
+        self.save(&waiting.commands).await;
+        waiting.commands.clone()
+    }
+
+    async fn save(&self, commands: &[PendingCommand]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let saved = async { replace_file(path, serde_json::to_vec(commands)?).await }.await;
+        if let Err(e) = saved {
+            warn!("Couldn't save the outbox: {:#}", e);
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<PendingCommand>> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+    };
+
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("Couldn't parse the outbox in {}", path.display()))
+}
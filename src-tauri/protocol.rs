@@ -0,0 +1,252 @@
+//! Wire format of the SignalR 2.x JSON protocol, as spoken by QHub.
+//!
+//! Everything sent to or received from the server goes through the types in this module, so the
+//! rest of the app never has to pick single-letter fields out of raw JSON.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::error::InboundError;
+
+pub(crate) const HUB_NAME: &str = "QHub";
+
+/// The only version of the persistent connection protocol we speak
+pub(crate) const PROTOCOL_VERSION: &str = "1.4";
+
+/// Argument list for hub methods which don't take any arguments
+pub(crate) const NO_ARGS: [(); 0] = [];
+
+#[derive(Serialize, Debug)]
+struct HubDescriptor<'a> {
+    #[serde(rename = "Name")]
+    name: &'a str,
+}
+
+/// Value of the `connectionData` query parameter, listing the hubs we want to talk to
+pub(crate) fn connection_data() -> String {
+    serde_json::to_string(&[HubDescriptor { name: HUB_NAME }])
+        .expect("Couldn't serialize connection data")
+}
+
+/// The server's answer to `negotiate`, describing the connection it's prepared to accept
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct NegotiateResponse {
+    #[serde(default)]
+    pub(crate) url: Option<String>,
+    pub(crate) connection_token: String,
+    #[serde(default)]
+    pub(crate) connection_id: Option<String>,
+    /// How long the server may go without sending a keep-alive. Absent if keep-alives are off.
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    /// How long the server remembers a dropped connection, i.e. how long it can be resumed for
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub(crate) disconnect_timeout: Duration,
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub(crate) connection_timeout: Option<Duration>,
+    #[serde(default)]
+    pub(crate) try_web_sockets: bool,
+    pub(crate) protocol_version: String,
+    /// How long a transport may take to connect before the client should give up on it
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub(crate) transport_connect_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub(crate) long_poll_delay: Option<Duration>,
+}
+
+impl NegotiateResponse {
+    pub(crate) fn check_protocol_version(&self) -> Result<()> {
+        if self.protocol_version != PROTOCOL_VERSION {
+            bail!(
+                "Server speaks protocol version {}, but only version {} is supported",
+                self.protocol_version,
+                PROTOCOL_VERSION
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// A call to a server-side hub method. `args` must serialize to a JSON array, so use a tuple,
+/// an array or [`NO_ARGS`].
+#[derive(Serialize, Debug)]
+pub(crate) struct HubInvocation<'a, A> {
+    #[serde(rename = "H")]
+    pub(crate) hub: &'a str,
+    #[serde(rename = "M")]
+    pub(crate) method: &'a str,
+    #[serde(rename = "A")]
+    pub(crate) args: A,
+    #[serde(rename = "I")]
+    pub(crate) id: u64,
+}
+
+impl<'a, A: Serialize> HubInvocation<'a, A> {
+    pub(crate) fn new(id: u64, method: &'a str, args: A) -> Self {
+        Self {
+            hub: HUB_NAME,
+            method,
+            args,
+            id,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .with_context(|| format!("Couldn't serialize {} invocation", self.method))
+    }
+}
+
+/// The server's reply to a [`HubInvocation`] with the same id
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct HubResult {
+    #[serde(rename = "I", deserialize_with = "deserialize_invocation_id")]
+    pub(crate) id: u64,
+    #[serde(rename = "R", default)]
+    pub(crate) result: Option<Value>,
+    #[serde(rename = "E", default)]
+    pub(crate) error: Option<String>,
+    #[serde(rename = "H", default)]
+    pub(crate) is_hub_exception: bool,
+    #[serde(rename = "D", default)]
+    pub(crate) error_data: Option<Value>,
+}
+
+impl HubResult {
+    /// Splits the reply into the method's return value or the error the hub raised
+    pub(crate) fn into_result(self) -> std::result::Result<Value, HubError> {
+        match self.error {
+            Some(message) => Err(HubError {
+                message,
+                is_hub_exception: self.is_hub_exception,
+                data: self.error_data,
+            }),
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+/// An error returned by the server in place of an invocation result
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HubError {
+    pub(crate) message: String,
+    pub(crate) is_hub_exception: bool,
+    pub(crate) data: Option<Value>,
+}
+
+impl fmt::Display for HubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hub error: {}", self.message)
+    }
+}
+
+impl std::error::Error for HubError {}
+
+/// A hub method the server invokes on the client, e.g. `NewQueue`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct HubMessage {
+    #[serde(rename = "H")]
+    pub(crate) hub: String,
+    #[serde(rename = "M")]
+    pub(crate) method: String,
+    #[serde(rename = "A", default)]
+    pub(crate) args: Vec<Value>,
+}
+
+/// A persistent-connection frame. An empty frame (`{}`) is a keep-alive.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub(crate) struct ConnectionFrame {
+    /// Cursor of the last message in this frame, needed to resume after a reconnect
+    #[serde(rename = "C", default)]
+    pub(crate) message_id: Option<String>,
+    #[serde(rename = "M", default)]
+    pub(crate) messages: Vec<HubMessage>,
+    /// Set on the first frame after the transport connects
+    #[serde(rename = "S", default)]
+    pub(crate) initialized: Option<u8>,
+    /// Token for the groups this connection belongs to, needed to resume after a reconnect
+    #[serde(rename = "G", default)]
+    pub(crate) groups_token: Option<String>,
+    #[serde(rename = "E", default)]
+    pub(crate) error: Option<String>,
+    /// Set when the server wants the client to reconnect
+    #[serde(rename = "T", default)]
+    pub(crate) should_reconnect: Option<u8>,
+    /// Delay in milliseconds before the next poll, only sent to long-polling clients
+    #[serde(rename = "L", default)]
+    pub(crate) long_poll_delay: Option<u64>,
+}
+
+/// Anything the server can send over the transport
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ServerMessage {
+    Result(HubResult),
+    Frame(ConnectionFrame),
+}
+
+impl ServerMessage {
+    pub(crate) fn parse(text: &str) -> Result<Self, InboundError> {
+        let value: Value = serde_json::from_str(text).map_err(InboundError::InvalidJson)?;
+
+        let Value::Object(object) = &value else {
+            return Err(InboundError::NotAnObject(value.to_string()));
+        };
+
+        if object.contains_key("I") {
+            serde_json::from_value(value)
+                .map(Self::Result)
+                .map_err(|source| InboundError::Malformed {
+                    what: "invocation result",
+                    source,
+                })
+        } else {
+            serde_json::from_value(value)
+                .map(Self::Frame)
+                .map_err(|source| InboundError::Malformed {
+                    what: "connection frame",
+                    source,
+                })
+        }
+    }
+}
+
+/// Timeouts in the negotiate response are given as fractional seconds
+fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| de::Error::custom(format!("Invalid timeout {} seconds", seconds)))
+}
+
+fn deserialize_optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    match Option::<f64>::deserialize(deserializer)? {
+        Some(seconds) => Duration::try_from_secs_f64(seconds)
+            .map(Some)
+            .map_err(|_| de::Error::custom(format!("Invalid timeout {} seconds", seconds))),
+        None => Ok(None),
+    }
+}
+
+/// The server echoes invocation ids back as strings, but accept numbers too
+fn deserialize_invocation_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum InvocationId {
+        Number(u64),
+        String(String),
+    }
+
+    match InvocationId::deserialize(deserializer)? {
+        InvocationId::Number(id) => Ok(id),
+        InvocationId::String(id) => id
+            .parse()
+            .map_err(|_| de::Error::custom(format!("Invalid invocation id {:?}", id))),
+    }
+}
@@ -1,16 +1,20 @@
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::InboundError;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct User {
-    pub(crate) username: String,
-    pub(crate) full_name: String,
-    pub(crate) email: String,
+pub struct User {
+    pub username: String,
+    pub full_name: String,
+    pub email: String,
 }
 
 #[derive(Serialize, Deserialize, FromPrimitive, Debug, Copy, Clone, Hash, Eq, PartialEq)]
-pub(crate) enum QueueStatus {
+pub enum QueueStatus {
     Open = 0,
     Started = 1,
     Closed = 2,
@@ -18,18 +22,135 @@ pub(crate) enum QueueStatus {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Queue {
-    pub(crate) id: u64,
-    pub(crate) name: String,
-    pub(crate) status: QueueStatus,
-    pub(crate) members: Vec<User>,
-    pub(crate) messages: Vec<Message>,
-    pub(crate) restrict_to_group: String,
+pub struct Queue {
+    pub id: u64,
+    pub name: String,
+    pub status: QueueStatus,
+    pub members: Vec<User>,
+    pub messages: Vec<Message>,
+    pub restrict_to_group: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Message {
-    pub(crate) content: String,
-    pub(crate) sender: User,
+pub struct Message {
+    pub content: String,
+    pub sender: User,
+}
+
+/// A queue as QHub sends it. Fields the server sometimes leaves out or nulls are optional here
+/// and defaulted when converting into a [`Queue`].
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct WireQueue {
+    id: u64,
+    name: String,
+    status: u64,
+    #[serde(default)]
+    members: Option<Vec<WireUser>>,
+    #[serde(default)]
+    messages: Option<Vec<WireMessage>>,
+    #[serde(default)]
+    restrict_to_group: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct WireUser {
+    user_name: String,
+    #[serde(default)]
+    full_name: Option<String>,
+    #[serde(default)]
+    email_address: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct WireMessage {
+    #[serde(default)]
+    content: Option<String>,
+    sender: WireUser,
+}
+
+impl From<WireUser> for User {
+    fn from(user: WireUser) -> Self {
+        Self {
+            username: user.user_name,
+            full_name: user.full_name.unwrap_or_default(),
+            email: user.email_address.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<WireMessage> for Message {
+    fn from(message: WireMessage) -> Self {
+        Self {
+            content: message.content.unwrap_or_default(),
+            sender: message.sender.into(),
+        }
+    }
+}
+
+impl TryFrom<WireQueue> for Queue {
+    type Error = InboundError;
+
+    fn try_from(queue: WireQueue) -> Result<Self, Self::Error> {
+        let status =
+            QueueStatus::from_u64(queue.status).ok_or(InboundError::UnknownQueueStatus {
+                id: queue.id,
+                status: queue.status,
+            })?;
+
+        Ok(Self {
+            id: queue.id,
+            name: queue.name,
+            status,
+            members: queue
+                .members
+                .unwrap_or_default()
+                .into_iter()
+                .map(User::from)
+                .collect(),
+            messages: queue
+                .messages
+                .unwrap_or_default()
+                .into_iter()
+                .map(Message::from)
+                .collect(),
+            restrict_to_group: queue.restrict_to_group.unwrap_or_default(),
+        })
+    }
+}
+
+impl Queue {
+    pub(crate) fn from_value(value: Value) -> Result<Self, InboundError> {
+        serde_json::from_value::<WireQueue>(value)
+            .map_err(|source| InboundError::Malformed {
+                what: "queue",
+                source,
+            })?
+            .try_into()
+    }
+}
+
+impl User {
+    pub(crate) fn from_value(value: Value) -> Result<Self, InboundError> {
+        serde_json::from_value::<WireUser>(value)
+            .map(User::from)
+            .map_err(|source| InboundError::Malformed {
+                what: "user",
+                source,
+            })
+    }
+}
+
+impl Message {
+    pub(crate) fn from_value(value: Value) -> Result<Self, InboundError> {
+        serde_json::from_value::<WireMessage>(value)
+            .map(Message::from)
+            .map_err(|source| InboundError::Malformed {
+                what: "message",
+                source,
+            })
+    }
 }
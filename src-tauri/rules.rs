@@ -0,0 +1,211 @@
+//! Rules deciding how the user is alerted to what happens to queues, kept in [`Settings`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::{ClientEvent, QueueChange};
+use crate::queue::{Message, Queue, User};
+use crate::settings::Settings;
+
+/// Matches queue events and says what to do about them. Every condition that's set has to match;
+/// one that isn't set matches anything.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationRule {
+    /// The queue's name, where `*` stands for any run of characters. Case is ignored.
+    pub queue_name: Option<String>,
+    /// The group the queue is restricted to
+    pub group: Option<String>,
+    /// Only queues the user is a member of
+    pub member_only: bool,
+    pub event: Option<EventKind>,
+    /// The username of whoever created, nagged or messaged the queue. Never matches status
+    /// changes, since the server doesn't say who made them.
+    pub sender: Option<String>,
+    /// Only messages that mention the user, by `@` and their username
+    pub mentioned: bool,
+    /// What to do about a matching event. Leaving this empty ignores it.
+    pub actions: Vec<NotificationAction>,
+}
+
+/// The kinds of queue event rules can match
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum EventKind {
+    NewQueue,
+    StatusChanged,
+    MembershipChanged,
+    Nagged,
+    Message,
+}
+
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationAction {
+    /// Bring the main window to the front
+    Popup,
+    /// Show a desktop notification
+    Notification,
+    /// Play a sound
+    Sound,
+}
+
+/// What a rule can know about an event
+struct Subject<'a> {
+    kind: EventKind,
+    queue: &'a Queue,
+    sender: Option<&'a User>,
+    message: Option<&'a Message>,
+}
+
+impl<'a> Subject<'a> {
+    fn of(event: &'a ClientEvent) -> Option<Self> {
+        let subject = match event {
+            ClientEvent::QueueChanged { change, queue } => Self {
+                kind: match change {
+                    QueueChange::Created => EventKind::NewQueue,
+                    QueueChange::StatusChanged => EventKind::StatusChanged,
+                    QueueChange::MembershipChanged => EventKind::MembershipChanged,
+                },
+                queue,
+                // Whoever creates a queue is its first member
+                sender: match change {
+                    QueueChange::Created => queue.members.first(),
+                    _ => None,
+                },
+                message: None,
+            },
+            ClientEvent::Nagged { queue, by } => Self {
+                kind: EventKind::Nagged,
+                queue,
+                sender: by.as_ref(),
+                message: None,
+            },
+            ClientEvent::MessageSent { queue, message } => Self {
+                kind: EventKind::Message,
+                queue,
+                sender: Some(&message.sender),
+                message: Some(message),
+            },
+            _ => return None,
+        };
+
+        Some(subject)
+    }
+
+    fn mentions(&self, username: &str) -> bool {
+        matches!(self.message, Some(message) if mentions(&message.content, username))
+    }
+
+    fn is_member(&self, username: &str) -> bool {
+        self.queue
+            .members
+            .iter()
+            .any(|member| member.username == username)
+    }
+}
+
+impl NotificationRule {
+    fn matches(&self, subject: &Subject, username: &str) -> bool {
+        let queue = subject.queue;
+
+        if let Some(pattern) = &self.queue_name {
+            if !glob_matches(pattern, &queue.name) {
+                return false;
+            }
+        }
+        if let Some(group) = &self.group {
+            if group != &queue.restrict_to_group {
+                return false;
+            }
+        }
+        if self.member_only && !subject.is_member(username) {
+            return false;
+        }
+        if let Some(event) = self.event {
+            if event != subject.kind {
+                return false;
+            }
+        }
+        if let Some(sender) = &self.sender {
+            if !matches!(subject.sender, Some(user) if &user.username == sender) {
+                return false;
+            }
+        }
+        if self.mentioned && !subject.mentions(username) {
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Settings {
+    /// How to alert the user to an event: the actions of the first rule that matches it, or
+    /// otherwise a desktop notification if they're turned on for that kind of event
+    pub fn notification_actions(&self, event: &ClientEvent) -> Vec<NotificationAction> {
+        let Some(subject) = Subject::of(event) else {
+            return Vec::new();
+        };
+
+        if let Some(rule) = self
+            .notification_rules
+            .iter()
+            .find(|rule| rule.matches(&subject, &self.username))
+        {
+            return rule.actions.clone();
+        }
+
+        let notify = match subject.kind {
+            EventKind::NewQueue => self.notifications.new_queue,
+            EventKind::StatusChanged => self.notifications.status_changed,
+            EventKind::MembershipChanged => false,
+            EventKind::Nagged => self.notifications.nagged,
+            // Nobody needs telling about their own messages
+            EventKind::Message => {
+                !matches!(subject.sender, Some(sender) if sender.username == self.username)
+                    && ((self.notifications.mentions && subject.mentions(&self.username))
+                        || (self.notifications.messages && subject.is_member(&self.username)))
+            }
+        };
+        if notify {
+            vec![NotificationAction::Notification]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Whether a message mentions someone, by `@` and their username in any case
+fn mentions(content: &str, username: &str) -> bool {
+    !username.is_empty()
+        && content
+            .to_lowercase()
+            .contains(&format!("@{}", username.to_lowercase()))
+}
+
+/// Case-insensitive matching where `*` matches any run of characters, including none
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and how much of the text it has swallowed so far
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, swallowed)) = backtrack {
+            p = star + 1;
+            t = swallowed + 1;
+            backtrack = Some((star, swallowed + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
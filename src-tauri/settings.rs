@@ -1,19 +1,23 @@
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Manager};
-use tauri_egui::{
-    eframe,
-    egui::{self, FontId, RichText, Vec2},
-    EguiPluginHandle,
+
+use crate::{
+    endpoint::{ServerEndpoint, DEFAULT_SERVER_URL},
+    rules::NotificationRule,
+    tls::tls_connector,
+    transport::TransportKind,
 };
 
-use crate::{connect, fetch_data, State};
+/// Name the settings are stored under by confy
+const APP_NAME: &str = "rq";
 
-#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
 #[allow(dead_code)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct Settings {
+pub struct Settings {
     #[serde(default)]
     pub email: String,
 
@@ -28,128 +32,124 @@ pub(crate) struct Settings {
 
     #[serde(default)]
     pub theme: Theme,
+
+    #[serde(default = "default_server_url")]
+    pub server_url: String,
+
+    /// PEM file with extra root certificates to trust, for servers using a private CA
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Skip certificate validation entirely, only meant for internal self-signed servers
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+
+    /// Only connect with this transport, instead of falling back from websockets as needed
+    #[serde(default)]
+    pub transport: Option<TransportKind>,
+
+    /// How often to list every queue again in case notifications went missing unnoticed. Zero
+    /// only lists them on connecting and when notifications are known to have been missed.
+    #[serde(default = "default_resync_interval_secs")]
+    pub resync_interval_secs: u64,
+
+    /// Which queue events show a desktop notification
+    #[serde(default)]
+    pub notifications: Notifications,
+
+    /// Checked in order before the toggles above, see [`NotificationRule`]
+    #[serde(default)]
+    pub notification_rules: Vec<NotificationRule>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Default, Copy, Clone)]
-#[non_exhaustive]
-pub(crate) enum Theme {
-    #[default]
-    Win98,
-    ClassicQ3,
-    Modern,
+fn default_server_url() -> String {
+    DEFAULT_SERVER_URL.to_owned()
 }
 
-struct SettingsApp {
-    settings: Settings,
-    on_submit: Arc<dyn Fn() + Send + Sync>,
+fn default_resync_interval_secs() -> u64 {
+    300
 }
 
-impl SettingsApp {
-    fn new(settings: Settings, on_submit: Arc<dyn Fn() + Send + Sync>) -> Self {
+impl Default for Settings {
+    fn default() -> Self {
         Self {
-            settings,
-            on_submit,
+            email: String::default(),
+            full_name: String::default(),
+            username: String::default(),
+            groups: Vec::default(),
+            theme: Theme::default(),
+            server_url: default_server_url(),
+            ca_bundle: None,
+            accept_invalid_certs: false,
+            transport: None,
+            resync_interval_secs: default_resync_interval_secs(),
+            notifications: Notifications::default(),
+            notification_rules: Vec::new(),
         }
     }
+}
+
+impl Settings {
+    pub(crate) fn server_endpoint(&self) -> anyhow::Result<ServerEndpoint> {
+        ServerEndpoint::parse(&self.server_url)
+    }
 
-    fn on_submit(&self) {
-        (self.on_submit)()
+    pub(crate) fn resync_interval(&self) -> Option<Duration> {
+        (self.resync_interval_secs > 0).then(|| Duration::from_secs(self.resync_interval_secs))
     }
-}
 
-impl eframe::App for SettingsApp {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let Settings {
-                email,
-                full_name,
-                username,
-                groups,
-                theme,
-            } = &mut self.settings;
-
-            let mut groups_text = groups.join(",");
-
-            ui.label(RichText::new("Settings").font(FontId::proportional(32.0)));
-
-            ui.label("Full Name");
-            let _full_name_field = ui.add_sized(
-                [ui.available_width(), 24.0],
-                egui::TextEdit::singleline(full_name),
-            );
-
-            ui.label("Email");
-            let _email_field = ui.add_sized(
-                [ui.available_width(), 24.0],
-                egui::TextEdit::singleline(email),
-            );
-
-            ui.label("Username");
-            let _username_field = ui.add_sized(
-                [ui.available_width(), 24.0],
-                egui::TextEdit::singleline(username),
-            );
-
-            ui.label("Groups");
-            let _groups_field = ui.add_sized(
-                [ui.available_width(), 24.0],
-                egui::TextEdit::singleline(&mut groups_text),
-            );
-
-            ui.label("Theme");
-            egui::ComboBox::from_id_source("theme_dropdown")
-                .width(ui.available_width() * 0.8)
-                .selected_text(format!("{:?}", theme))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(theme, Theme::Win98, "Windows 98");
-                    ui.selectable_value(theme, Theme::ClassicQ3, "ClassicQ3");
-                    // ui.selectable_value(theme, Theme::Win98, "Windows 98")
-                });
-
-            if ui.button("Submit").clicked() {
-                *groups = groups_text.split(',').map(ToOwned::to_owned).collect();
-
-                confy::store("rq", None, self.settings.clone()).unwrap();
-                frame.close();
-                self.on_submit();
-            }
-        });
+    /// Reads the settings stored for the current user, or the defaults if there are none yet
+    pub fn load() -> anyhow::Result<Self> {
+        confy::load(APP_NAME, None).context("Couldn't load settings")
+    }
+
+    pub fn store(&self) -> anyhow::Result<()> {
+        confy::store(APP_NAME, None, self).context("Couldn't store settings")
+    }
+
+    /// Checks everything needed to connect, so bad settings are rejected before they're stored
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.server_endpoint()?;
+        tls_connector(self)?;
+
+        Ok(())
     }
 }
 
-impl Settings {
-    pub fn launch(self, app: AppHandle) {
-        let egui_handle = app.state::<EguiPluginHandle>();
-
-        let native_options = eframe::NativeOptions {
-            resizable: false,
-            initial_window_size: Some(Vec2::new(500.0, 400.0)),
-            ..Default::default()
-        };
-
-        let app = app.clone();
-        let _window = egui_handle
-            .create_window(
-                "settings".to_owned(),
-                Box::new(move |_| {
-                    Box::new(SettingsApp::new(
-                        self,
-                        Arc::new(move || {
-                            let app = app.clone();
-                            let state = app.state::<State>();
-                            state.cancel_websockets.notify_waiters();
-                            tauri::async_runtime::spawn(async move {
-                                let state = app.state();
-                                let app = app.clone();
-                                let _ = connect(app.clone()).await;
-                                let _ = fetch_data(app, state).await;
-                            });
-                        }),
-                    ))
-                }),
-                "Settings".to_owned(),
-                native_options,
-            )
-            .unwrap();
+/// Per-event toggles for desktop notifications, for events no [`NotificationRule`] matches. Events
+/// missing from stored settings are notified.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Notifications {
+    /// Someone created a queue
+    pub new_queue: bool,
+    /// A queue was started, reset or closed
+    pub status_changed: bool,
+    /// Someone nagged a queue
+    pub nagged: bool,
+    /// Someone sent a message to a queue the user is in
+    pub messages: bool,
+    /// Someone mentioned the user in a message to any queue
+    pub mentions: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            new_queue: true,
+            status_changed: true,
+            nagged: true,
+            messages: true,
+            mentions: true,
+        }
     }
 }
+
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Default, Copy, Clone)]
+#[non_exhaustive]
+pub enum Theme {
+    #[default]
+    Win98,
+    ClassicQ3,
+    Modern,
+}
@@ -0,0 +1,210 @@
+//! The settings window, drawn with egui.
+
+use std::{path::PathBuf, sync::Arc};
+
+use rq::{QHubClient, Settings, Theme, TransportKind};
+use tauri::{AppHandle, Manager};
+use tauri_egui::{
+    eframe,
+    egui::{self, FontId, RichText, Vec2},
+    EguiPluginHandle,
+};
+use tracing::log::warn;
+
+use crate::emit_queues;
+
+struct SettingsApp {
+    settings: Settings,
+    on_submit: Arc<dyn Fn(Settings) + Send + Sync>,
+    ca_bundle_text: String,
+    error: Option<String>,
+}
+
+impl SettingsApp {
+    fn new(settings: Settings, on_submit: Arc<dyn Fn(Settings) + Send + Sync>) -> Self {
+        let ca_bundle_text = settings
+            .ca_bundle
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+
+        Self {
+            settings,
+            on_submit,
+            ca_bundle_text,
+            error: None,
+        }
+    }
+
+    fn on_submit(&self) {
+        (self.on_submit)(self.settings.clone())
+    }
+}
+
+impl eframe::App for SettingsApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Settings {
+                email,
+                full_name,
+                username,
+                groups,
+                theme,
+                server_url,
+                ca_bundle,
+                accept_invalid_certs,
+                transport,
+                resync_interval_secs,
+                notifications,
+                notification_rules,
+            } = &mut self.settings;
+
+            let mut groups_text = groups.join(",");
+
+            ui.label(RichText::new("Settings").font(FontId::proportional(32.0)));
+
+            ui.label("Full Name");
+            let _full_name_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(full_name),
+            );
+
+            ui.label("Email");
+            let _email_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(email),
+            );
+
+            ui.label("Username");
+            let _username_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(username),
+            );
+
+            ui.label("Groups");
+            let _groups_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(&mut groups_text),
+            );
+
+            ui.label("Theme");
+            egui::ComboBox::from_id_source("theme_dropdown")
+                .width(ui.available_width() * 0.8)
+                .selected_text(format!("{:?}", theme))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(theme, Theme::Win98, "Windows 98");
+                    ui.selectable_value(theme, Theme::ClassicQ3, "ClassicQ3");
+                    // ui.selectable_value(theme, Theme::Win98, "Windows 98")
+                });
+
+            ui.label("Server URL");
+            let _server_url_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(server_url),
+            );
+
+            ui.label("CA Bundle (PEM)");
+            let _ca_bundle_field = ui.add_sized(
+                [ui.available_width(), 24.0],
+                egui::TextEdit::singleline(&mut self.ca_bundle_text),
+            );
+
+            ui.checkbox(accept_invalid_certs, "Accept invalid certificates");
+
+            ui.label("Transport");
+            egui::ComboBox::from_id_source("transport_dropdown")
+                .width(ui.available_width() * 0.8)
+                .selected_text(match transport {
+                    Some(transport) => transport.to_string(),
+                    None => "Automatic".to_owned(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(transport, None, "Automatic");
+                    for kind in TransportKind::ALL {
+                        ui.selectable_value(transport, Some(kind), kind.to_string());
+                    }
+                });
+
+            ui.label("Relist every queue every (seconds, 0 for never)");
+            ui.add(egui::DragValue::new(resync_interval_secs).clamp_range(0..=86400));
+
+            ui.label("Notify me when");
+            ui.checkbox(&mut notifications.new_queue, "A queue is created");
+            ui.checkbox(
+                &mut notifications.status_changed,
+                "A queue is started, reset or closed",
+            );
+            ui.checkbox(&mut notifications.nagged, "Someone nags a queue");
+            ui.checkbox(
+                &mut notifications.messages,
+                "Someone messages a queue I'm in",
+            );
+            ui.checkbox(
+                &mut notifications.mentions,
+                "Someone mentions me in a message",
+            );
+            if !notification_rules.is_empty() {
+                ui.label(format!(
+                    "{} notification rules from the settings file are checked first",
+                    notification_rules.len()
+                ));
+            }
+
+            if let Some(error) = &self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+
+            if ui.button("Submit").clicked() {
+                *groups = groups_text.split(',').map(ToOwned::to_owned).collect();
+                let ca_bundle_text = self.ca_bundle_text.trim();
+                *ca_bundle = (!ca_bundle_text.is_empty()).then(|| PathBuf::from(ca_bundle_text));
+
+                if let Err(e) = self.settings.validate() {
+                    self.error = Some(format!("{:#}", e));
+                    return;
+                }
+
+                self.settings.store().unwrap();
+                frame.close();
+                self.on_submit();
+            }
+        });
+    }
+}
+
+pub(crate) fn launch(settings: Settings, app: AppHandle) {
+    let egui_handle = app.state::<EguiPluginHandle>();
+
+    let native_options = eframe::NativeOptions {
+        resizable: false,
+        initial_window_size: Some(Vec2::new(500.0, 530.0)),
+        ..Default::default()
+    };
+
+    let app = app.clone();
+    let _window = egui_handle
+        .create_window(
+            "settings".to_owned(),
+            Box::new(move |_| {
+                Box::new(SettingsApp::new(
+                    settings,
+                    Arc::new(move |settings| {
+                        let app = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            let client = app.state::<QHubClient>();
+                            let _ = client
+                                .update_settings(settings)
+                                .await
+                                .inspect_err(|e| warn!("Couldn't apply settings: {:#}", e));
+                            let _ = emit_queues(&app)
+                                .await
+                                .inspect_err(|e| warn!("Couldn't update the UI: {:#}", e));
+                        });
+                    }),
+                ))
+            }),
+            "Settings".to_owned(),
+            native_options,
+        )
+        .unwrap();
+}
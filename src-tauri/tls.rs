@@ -0,0 +1,45 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use native_tls::{Certificate, TlsConnector};
+
+use crate::settings::Settings;
+
+const PEM_BEGIN_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END_CERTIFICATE: &str = "-----END CERTIFICATE-----";
+
+/// Builds the TLS connector shared by the negotiate request and the websocket connection
+pub(crate) fn tls_connector(settings: &Settings) -> Result<TlsConnector> {
+    let mut builder = TlsConnector::builder();
+
+    if let Some(path) = &settings.ca_bundle {
+        for certificate in load_ca_bundle(path)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+
+    builder.danger_accept_invalid_certs(settings.accept_invalid_certs);
+
+    builder.build().context("Couldn't build TLS connector")
+}
+
+/// Reads every certificate from a PEM file, which may contain a whole chain
+fn load_ca_bundle(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read CA bundle {}", path.display()))?;
+
+    let certificates = pem
+        .split_inclusive(PEM_END_CERTIFICATE)
+        .filter(|block| block.contains(PEM_BEGIN_CERTIFICATE))
+        .map(|block| {
+            Certificate::from_pem(block.trim().as_bytes())
+                .with_context(|| format!("Invalid certificate in CA bundle {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if certificates.is_empty() {
+        bail!("No certificates found in CA bundle {}", path.display());
+    }
+
+    Ok(certificates)
+}
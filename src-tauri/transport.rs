@@ -0,0 +1,358 @@
+//! The ways a SignalR connection can carry messages. Websockets are preferred, but some proxies
+//! strip the upgrade, so server-sent events and long polling are available as fallbacks.
+//!
+//! Every transport is split in two: a [`TransportSender`], shared by everything that talks to the
+//! server, and a [`TransportReceiver`], owned by the reader.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures_util::future::{self, BoxFuture};
+use futures_util::sink::SinkExt;
+use futures_util::stream::{self, BoxStream, SplitSink, SplitStream};
+use futures_util::{FutureExt, StreamExt};
+use reqwest::{Method, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message as WebsocketMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::log::debug;
+
+use crate::connection::{PersistentConnection, Session};
+use crate::error::InboundError;
+use crate::protocol::ServerMessage;
+
+pub(crate) type Websocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Server-sent events are separated by a blank line
+const EVENT_SEPARATOR: &[u8] = b"\n\n";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransportKind {
+    WebSockets,
+    ServerSentEvents,
+    LongPolling,
+}
+
+impl TransportKind {
+    /// Every transport, in the order they're tried when none is forced
+    pub const ALL: [Self; 3] = [Self::WebSockets, Self::ServerSentEvents, Self::LongPolling];
+
+    /// Value of the `transport` query parameter
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::WebSockets => "webSockets",
+            Self::ServerSentEvents => "serverSentEvents",
+            Self::LongPolling => "longPolling",
+        }
+    }
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSockets => write!(f, "WebSockets"),
+            Self::ServerSentEvents => write!(f, "Server-Sent Events"),
+            Self::LongPolling => write!(f, "Long Polling"),
+        }
+    }
+}
+
+/// Anything a transport receives from the server
+#[derive(Debug)]
+pub(crate) enum Inbound {
+    /// A SignalR message, still to be parsed
+    Text(String),
+    /// Proof the connection is alive that doesn't carry a message, e.g. a websocket pong
+    KeepAlive,
+    /// Something the transport couldn't make sense of, which is skipped
+    Invalid(InboundError),
+}
+
+/// Sending half of a transport
+pub(crate) trait TransportSender: Send + fmt::Debug {
+    /// Sends a serialized message, such as a hub invocation, to the server
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<()>>;
+
+    /// Checks the server is still there. Fails once the transport can't be written to.
+    fn ping(&mut self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Receiving half of a transport, which ends when the server closes the connection
+pub(crate) type TransportReceiver = BoxStream<'static, Result<Inbound>>;
+
+/// A freshly opened transport, before its halves are handed out
+pub(crate) struct Transport {
+    pub(crate) sender: Box<dyn TransportSender>,
+    pub(crate) receiver: TransportReceiver,
+}
+
+impl Transport {
+    pub(crate) fn websocket(websocket: Websocket) -> Self {
+        let (sink, source) = websocket.split();
+
+        Self {
+            sender: Box::new(WebsocketSender(sink)),
+            receiver: websocket_receiver(source),
+        }
+    }
+
+    /// A transport which receives over `receiver` and sends with separate HTTP requests
+    fn http(
+        connection: PersistentConnection,
+        session: Session,
+        receiver: TransportReceiver,
+    ) -> Self {
+        let (replies_tx, replies_rx) = mpsc::unbounded_channel();
+
+        Self {
+            sender: Box::new(HttpSender {
+                connection,
+                session,
+                replies: replies_tx,
+            }),
+            receiver: with_replies(receiver, replies_rx),
+        }
+    }
+
+    pub(crate) fn server_sent_events(
+        connection: PersistentConnection,
+        session: Session,
+        response: Response,
+    ) -> Self {
+        Self::http(connection, session, server_sent_events(response))
+    }
+
+    /// Long polling has no connection to speak of, so the first poll does the connecting.
+    /// `first_url` is the connect or reconnect url, depending on whether `session` is new.
+    pub(crate) fn long_polling(
+        connection: PersistentConnection,
+        session: Session,
+        first_url: reqwest::Url,
+    ) -> Self {
+        let receiver = long_poll(connection.clone(), session.clone(), first_url);
+        Self::http(connection, session, receiver)
+    }
+}
+
+#[derive(Debug)]
+struct WebsocketSender(SplitSink<Websocket, WebsocketMessage>);
+
+impl TransportSender for WebsocketSender {
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<()>> {
+        async move { Ok(self.0.send(WebsocketMessage::text(message)).await?) }.boxed()
+    }
+
+    fn ping(&mut self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            Ok(self
+                .0
+                .send(WebsocketMessage::Ping(vec![1, 3, 3, 7, 4, 2, 0]))
+                .await?)
+        }
+        .boxed()
+    }
+}
+
+fn websocket_receiver(source: SplitStream<Websocket>) -> TransportReceiver {
+    source
+        .map(|message| {
+            Ok(match message? {
+                WebsocketMessage::Text(body) => Inbound::Text(body),
+                WebsocketMessage::Binary(data) => {
+                    Inbound::Invalid(InboundError::UnexpectedBinary(data.len()))
+                }
+                WebsocketMessage::Close(frame) => {
+                    debug!("Server is closing the connection: {:?}", frame);
+                    Inbound::KeepAlive
+                }
+                // tungstenite queues the matching pong itself
+                WebsocketMessage::Ping(_)
+                | WebsocketMessage::Pong(_)
+                | WebsocketMessage::Frame(_) => Inbound::KeepAlive,
+            })
+        })
+        .boxed()
+}
+
+/// Sends each message as its own request, for transports that only receive over the connection
+#[derive(Debug)]
+struct HttpSender {
+    connection: PersistentConnection,
+    session: Session,
+    /// Invocation results come back in the response to the request that sent the invocation,
+    /// so they're passed on to the receiver from here
+    replies: mpsc::UnboundedSender<Inbound>,
+}
+
+impl TransportSender for HttpSender {
+    fn send(&mut self, message: String) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let reply = self
+                .connection
+                .transport_request(
+                    Method::POST,
+                    self.connection.endpoint().send_url(),
+                    &self.session,
+                )
+                .form(&[("data", message)])
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            if !reply.trim().is_empty() {
+                // The receiver is only gone if the connection is being torn down anyway
+                let _ = self.replies.send(Inbound::Text(reply));
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn ping(&mut self) -> BoxFuture<'_, Result<()>> {
+        async move {
+            let response = self
+                .connection
+                .request(Method::GET, self.connection.endpoint().ping_url())
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Value>()
+                .await?;
+
+            match response.get("Response").and_then(Value::as_str) {
+                Some("pong") => Ok(()),
+                _ => bail!("Unexpected ping response: {}", response),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Merges replies to sent messages into a receiver, ending when the receiver itself ends
+fn with_replies(
+    receiver: TransportReceiver,
+    replies: mpsc::UnboundedReceiver<Inbound>,
+) -> TransportReceiver {
+    let receiver = receiver.map(Some).chain(stream::once(future::ready(None)));
+    let replies = stream::unfold(replies, |mut replies| async move {
+        let reply = replies.recv().await?;
+        Some((Some(Ok(reply)), replies))
+    });
+
+    stream::select(receiver, replies)
+        .take_while(|inbound| future::ready(inbound.is_some()))
+        .filter_map(future::ready)
+        .boxed()
+}
+
+/// Splits a `text/event-stream` response into the data of each event
+fn server_sent_events(response: Response) -> TransportReceiver {
+    stream::unfold(Some((response, Vec::new())), |state| async move {
+        let (mut response, mut buffer) = state?;
+
+        loop {
+            if let Some(end) = find(&buffer, EVENT_SEPARATOR) {
+                let event: Vec<u8> = buffer.drain(..end + EVENT_SEPARATOR.len()).collect();
+                let data = String::from_utf8_lossy(&event)
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                let inbound = match data.as_str() {
+                    // Sent as soon as the stream opens, ahead of the initialization frame
+                    "" | "initialized" => Inbound::KeepAlive,
+                    _ => Inbound::Text(data),
+                };
+                return Some((Ok(inbound), Some((response, buffer))));
+            }
+
+            match response.chunk().await {
+                Ok(Some(chunk)) => buffer.extend(chunk.iter().filter(|&&byte| byte != b'\r')),
+                Ok(None) => return None,
+                Err(e) => return Some((Err(e.into()), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Polls for messages until a poll fails, resuming each poll from the last message received
+fn long_poll(
+    connection: PersistentConnection,
+    session: Session,
+    first_url: reqwest::Url,
+) -> TransportReceiver {
+    struct Poll {
+        connection: PersistentConnection,
+        session: Session,
+        url: reqwest::Url,
+        delay: Duration,
+    }
+
+    let poll = Poll {
+        connection,
+        session,
+        url: first_url,
+        delay: Duration::ZERO,
+    };
+
+    stream::unfold(Some(poll), |poll| async move {
+        let mut poll = poll?;
+        sleep(poll.delay).await;
+
+        let mut cursor = Vec::new();
+        if let Some(message_id) = &poll.session.message_id {
+            cursor.push(("messageId", message_id.clone()));
+        }
+        if let Some(groups_token) = &poll.session.groups_token {
+            cursor.push(("groupsToken", groups_token.clone()));
+        }
+
+        let body = async {
+            poll.connection
+                .transport_request(Method::POST, poll.url.clone(), &poll.session)
+                .form(&cursor)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await;
+
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => return Some((Err(e.into()), None)),
+        };
+
+        poll.url = poll.connection.endpoint().poll_url();
+        if body.trim().is_empty() {
+            return Some((Ok(Inbound::KeepAlive), Some(poll)));
+        }
+
+        if let Ok(ServerMessage::Frame(frame)) = ServerMessage::parse(&body) {
+            poll.session.advance(&frame);
+            poll.delay = Duration::from_millis(frame.long_poll_delay.unwrap_or_default());
+        }
+
+        Some((Ok(Inbound::Text(body)), Some(poll)))
+    })
+    .boxed()
+}
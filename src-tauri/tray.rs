@@ -0,0 +1,40 @@
+//! The system tray icon and menu, which double as a connection status indicator.
+
+use rq::ConnectionStatus;
+use tauri::{AppHandle, CustomMenuItem, Icon, SystemTray, SystemTrayMenu, SystemTrayMenuItem};
+
+const STATUS_ITEM: &str = "status";
+
+const CONNECTED_ICON: &[u8] = include_bytes!("../icons/icon.png");
+const DISCONNECTED_ICON: &[u8] = include_bytes!("../icons/tray-disconnected.png");
+
+pub(crate) fn system_tray() -> SystemTray {
+    let status = CustomMenuItem::new(STATUS_ITEM.to_string(), "Disconnected").disabled();
+    let quit = CustomMenuItem::new("quit".to_string(), "Quit");
+    let tray_menu = SystemTrayMenu::new()
+        .add_item(status)
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(quit);
+
+    SystemTray::new().with_menu(tray_menu)
+}
+
+pub(crate) fn show_connection_status(
+    app: &AppHandle,
+    status: &ConnectionStatus,
+) -> tauri::Result<()> {
+    app.tray_handle()
+        .get_item(STATUS_ITEM)
+        .set_title(status.summary())
+}
+
+/// Greys out the tray icon while disconnected
+pub(crate) fn show_connected(app: &AppHandle, connected: bool) -> tauri::Result<()> {
+    let icon = if connected {
+        CONNECTED_ICON
+    } else {
+        DISCONNECTED_ICON
+    };
+
+    app.tray_handle().set_icon(Icon::Raw(icon.to_vec()))
+}
@@ -0,0 +1,104 @@
+//! Everything sent to the server goes through a single writer task per connection. Callers queue
+//! their messages and wait to hear they were written, so a slow write only holds up the messages
+//! behind it, and nobody holds a lock while waiting on the network.
+
+use anyhow::{anyhow, Result};
+use tokio::select;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::log::debug;
+
+use crate::transport::TransportSender;
+
+/// How many messages can be waiting to be written before senders have to wait their turn
+const WRITER_CAPACITY: usize = 32;
+
+#[derive(Debug)]
+enum Outbound {
+    Message(String),
+    Ping,
+}
+
+#[derive(Debug)]
+struct Write {
+    outbound: Outbound,
+    /// Told how the write went once it's done
+    done: oneshot::Sender<Result<()>>,
+}
+
+/// A handle to the writer for one connection. Clones queue to the same writer.
+#[derive(Debug, Clone)]
+pub(crate) struct Writer {
+    writes: mpsc::Sender<Write>,
+    cancel: CancellationToken,
+}
+
+impl Writer {
+    /// Starts writing to `sender` until `cancel` is cancelled, which also abandons any write in
+    /// progress and fails everything still queued
+    pub(crate) fn spawn(sender: Box<dyn TransportSender>, cancel: CancellationToken) -> Self {
+        let (writes, queued) = mpsc::channel(WRITER_CAPACITY);
+        tokio::spawn(write(sender, queued, cancel.clone()));
+
+        Self { writes, cancel }
+    }
+
+    /// Sends a serialized message, such as a hub invocation, returning once it's been written
+    pub(crate) async fn send(&self, message: String) -> Result<()> {
+        self.queue(Outbound::Message(message)).await
+    }
+
+    /// Checks the server is still there. Fails once the connection can't be written to.
+    pub(crate) async fn ping(&self) -> Result<()> {
+        self.queue(Outbound::Ping).await
+    }
+
+    async fn queue(&self, outbound: Outbound) -> Result<()> {
+        let cancelled = || anyhow!("Connection was reset before the message was sent");
+        let (done, written) = oneshot::channel();
+
+        // Cancelled tokens stay cancelled, so this can't miss a reset that's already happened
+        select! {
+            biased;
+            _ = self.cancel.cancelled() => return Err(cancelled()),
+            queued = self.writes.send(Write { outbound, done }) => {
+                queued.map_err(|_| cancelled())?
+            }
+        }
+
+        // The writer always answers, unless it's stopped
+        written.await.map_err(|_| cancelled())?
+    }
+}
+
+async fn write(
+    mut sender: Box<dyn TransportSender>,
+    mut queued: mpsc::Receiver<Write>,
+    cancel: CancellationToken,
+) {
+    loop {
+        let Write { outbound, done } = select! {
+            biased;
+            _ = cancel.cancelled() => break,
+            write = queued.recv() => match write {
+                Some(write) => write,
+                None => break,
+            },
+        };
+
+        let written = select! {
+            biased;
+            _ = cancel.cancelled() => break,
+            written = async {
+                match outbound {
+                    Outbound::Message(message) => sender.send(message).await,
+                    Outbound::Ping => sender.ping().await,
+                }
+            } => written,
+        };
+        // Whoever queued it may have given up waiting, which is fine
+        let _ = done.send(written);
+    }
+
+    debug!("Writer stopped");
+}
@@ -1,9 +1,11 @@
-import { useCallback, useEffect, useRef, useState } from "preact/hooks";
+import { useCallback, useEffect, useMemo, useRef, useState } from "preact/hooks";
 import { listen, TauriEvent } from "@tauri-apps/api/event";
 import { QueueCard } from "@/components/queuecard";
 import { invoke } from "@tauri-apps/api/tauri";
 import { Queue } from "@/data/queue";
 import { User } from "@/data/user";
+import { applyDiffs, QueueDiff } from "@/data/diff";
+import { describeCommand, PendingCommand } from "@/data/outbox";
 import useSize from "@react-hook/size";
 import { appWindow, currentMonitor, PhysicalSize } from "@tauri-apps/api/window";
 
@@ -19,17 +21,36 @@ import { ClickEvent, ControlledMenu, MenuItem, useMenuState } from "@szhsin/reac
 import { exit } from "@tauri-apps/api/process";
 
 import { Settings, Theme } from "@/data/settings";
+import { ConnectionStatus, describeConnection } from "@/data/connection";
 
-import { themeCssMap } from "@/utilities";
+import { playChime, themeCssMap } from "@/utilities";
 import { event } from "@tauri-apps/api";
 
 interface PollDataResponse {
   queues: [Queue];
   config: Settings;
+  /** The queues are the ones cached last time, not yet confirmed by the server */
+  stale: boolean;
+  /** Commands issued while disconnected, oldest first */
+  outbox: PendingCommand[];
 }
 
+const noPending: PendingCommand[] = [];
+
 export const App = () => {
   const [queues, setQueues] = useState<Record<number, Queue>>();
+  const [stale, setStale] = useState(false);
+  const [outbox, setOutbox] = useState<PendingCommand[]>([]);
+  // Grouped up front, so cards whose commands haven't changed don't redraw
+  const pendingByQueue = useMemo(() => {
+    const byQueue: Record<number, PendingCommand[]> = {};
+    for (const pending of outbox) {
+      if ("id" in pending.command) {
+        (byQueue[pending.command.id] ??= []).push(pending);
+      }
+    }
+    return byQueue;
+  }, [outbox]);
 
   const [currentUser, setCurrentUser] = useState<User>({
     username: "",
@@ -38,6 +59,7 @@ export const App = () => {
   });
   const [theme, setTheme] = useState<Theme>("Win98");
   const [hiddenQueues, setHiddenQueues] = useState(new Set());
+  const [connectionStatus, setConnectionStatus] = useState<ConnectionStatus>();
 
   const hideQueue = useCallback(
     (id: number) => {
@@ -78,10 +100,16 @@ export const App = () => {
   useEffect(() => {
     let unlistenQueuesUpdated: (() => void) | null = null;
     let unlistenWindowMove: (() => void) | null = null;
+    let unlistenConnectionStatus: (() => void) | null = null;
+    let unlistenPlaySound: (() => void) | null = null;
+    let unlistenQueuesDiffed: (() => void) | null = null;
+    let unlistenOutboxChanged: (() => void) | null = null;
     // let unlistenMousePosition: (() => void) | null = null;
     async function fetchData() {
-      unlistenQueuesUpdated = await listen<PollDataResponse>("data_updated", ({ payload: { config, queues } }) => {
+      unlistenQueuesUpdated = await listen<PollDataResponse>("data_updated", ({ payload: { config, queues, stale, outbox } }) => {
         setQueues(queues);
+        setStale(stale);
+        setOutbox(outbox);
         setCurrentUser({
           email: config.email,
           username: config.username,
@@ -96,12 +124,31 @@ export const App = () => {
         setMaxHeight(((monitor?.size.height ?? 0) * 0.9) / scaleFactor);
       });
 
+      unlistenConnectionStatus = await listen<ConnectionStatus>("connection_status", ({ payload }) => {
+        setConnectionStatus(payload);
+      });
+
+      unlistenPlaySound = await listen("play_sound", playChime);
+
+      unlistenQueuesDiffed = await listen<QueueDiff[]>("queues_diffed", ({ payload }) => {
+        setQueues((queues) => applyDiffs(queues ?? {}, payload));
+      });
+
+      unlistenOutboxChanged = await listen<PendingCommand[]>("outbox_changed", ({ payload }) => {
+        setOutbox(payload);
+      });
+
       await invoke("fetch_data");
+      setConnectionStatus(await invoke<ConnectionStatus>("fetch_connection_status"));
     }
     fetchData();
     return () => {
       unlistenQueuesUpdated?.();
       unlistenWindowMove?.();
+      unlistenConnectionStatus?.();
+      unlistenPlaySound?.();
+      unlistenQueuesDiffed?.();
+      unlistenOutboxChanged?.();
       // unlistenMousePosition && unlistenMousePosition();
     };
   }, []);
@@ -155,13 +202,32 @@ export const App = () => {
           <MenuItem value="restore">Restore Hidden Items</MenuItem>
           <MenuItem value="settings">Settings</MenuItem>
         </ControlledMenu>
+        {connectionStatus && connectionStatus.state.kind !== "connected" && (
+          <div class="connection-banner" title={connectionStatus.lastError ?? undefined}>
+            {describeConnection(connectionStatus)}
+            {stale && " · showing queues from last time"}
+          </div>
+        )}
+        {outbox
+          .filter(({ command }) => command.kind === "newQueue")
+          .map(({ id, command }) => (
+            <div key={id} class="connection-banner pending">
+              {describeCommand(command)} waiting to be sent
+            </div>
+          ))}
         <div class="shrink-wrapper" style={{ overflowY: "auto" }}>
           <div ref={queueContainerRef} class="queues-container">
             {
               Object.entries(queues ?? {})
               ?.filter(([, queue]) => !hiddenQueues.has(queue.id))
               .map(([, queue]) => (
-                <QueueCard key={queue.id} queue={queue} user={currentUser} onHide={hideQueue} />
+                <QueueCard
+                  key={queue.id}
+                  queue={queue}
+                  user={currentUser}
+                  pending={pendingByQueue[queue.id] ?? noPending}
+                  onHide={hideQueue}
+                />
               ))}
           </div>
         </div>
@@ -1,4 +1,6 @@
 import { Queue } from "@/data/queue";
+import { describeCommand, PendingCommand } from "@/data/outbox";
+import { CommandError, describeError } from "@/data/error";
 import { User } from "@/data/user";
 import { getGravatarUrl, getToastImage, userInQueue } from "@/utilities";
 import { ClickEvent, ControlledMenu, MenuItem, useMenuState } from "@szhsin/react-menu";
@@ -10,10 +12,12 @@ import { useMemo, useRef, useState } from "preact/hooks";
 interface QueueCardProps {
   queue: Queue;
   user: User;
+  /** Commands for this queue waiting to be sent once connected */
+  pending: PendingCommand[];
   onHide: (id: number) => void;
 }
 
-export const QueueCard = memo(({ queue, user, onHide }: QueueCardProps) => {
+export const QueueCard = memo(({ queue, user, pending, onHide }: QueueCardProps) => {
   const name = queue.name;
   const members = useMemo(() => queue.members.map((user) => getUserImage(user, "queue-member")), [queue.members]);
 
@@ -39,6 +43,11 @@ export const QueueCard = memo(({ queue, user, onHide }: QueueCardProps) => {
         <div class="queue-name">
           {name.split(/(luna)/i).map((s) => (s.toLowerCase() === "luna" ? <span class="luna">{s}</span> : s))}
         </div>
+        {pending.map(({ id, command }) => (
+          <div key={id} class="queue-pending">
+            {describeCommand(command)} waiting to be sent
+          </div>
+        ))}
         <div class="queue-image-container">
           {imageUrl ? (
             <img class="queue-image" alt="Image corresponding to the hashtag shown in the queue name" src={imageUrl} />
@@ -156,32 +165,37 @@ export const QueueCard = memo(({ queue, user, onHide }: QueueCardProps) => {
   );
 });
 
+/** Runs a queue command, which only fails if the server can't be reached or refuses it */
+const run = (command: string, args: Record<string, unknown>) => {
+  invoke(command, args).catch((error: CommandError) => console.error(describeError(error)));
+};
+
 const leaveQueue = (id: number) => {
-  invoke("leave_queue", { id });
+  run("leave_queue", { id });
 };
 
 const joinQueue = (id: number) => {
-  invoke("join_queue", { id });
+  run("join_queue", { id });
 };
 
 const messageQueue = (id: number, content: string) => {
-  invoke("message_queue", { id, content });
+  run("message_queue", { id, content });
 };
 
 const deleteQueue = (id: number) => {
-  invoke("delete_queue", { id });
+  run("delete_queue", { id });
 };
 
 const nagQueue = (id: number) => {
-  invoke("nag_queue", { id });
+  run("nag_queue", { id });
 };
 
 const startQueue = (id: number) => {
-  invoke("start_queue", { id });
+  run("start_queue", { id });
 };
 
 const resetQueue = (id: number) => {
-  invoke("reset_queue", { id });
+  run("reset_queue", { id });
 };
 
 const getUserImage = (user: User, className: string) => {
@@ -6,7 +6,8 @@ import "@/css/app.css";
 import "@/css/themes/98/98.scss";
 import useSize from "@react-hook/size";
 
-import { Settings, themes } from "@/data/settings";
+import { Settings, themes, Transport, transports } from "@/data/settings";
+import { CommandError, describeError } from "@/data/error";
 
 const SettingsComponent = () => {
   const fullNameEl = useRef<HTMLInputElement>(null);
@@ -14,11 +15,22 @@ const SettingsComponent = () => {
   const emailEl = useRef<HTMLInputElement>(null);
   const groupsEl = useRef<HTMLInputElement>(null);
   const themeEl = useRef<HTMLSelectElement>(null);
+  const serverUrlEl = useRef<HTMLInputElement>(null);
+  const caBundleEl = useRef<HTMLInputElement>(null);
+  const acceptInvalidCertsEl = useRef<HTMLInputElement>(null);
+  const transportEl = useRef<HTMLSelectElement>(null);
+  const resyncIntervalEl = useRef<HTMLInputElement>(null);
+  const notifyNewQueueEl = useRef<HTMLInputElement>(null);
+  const notifyStatusChangedEl = useRef<HTMLInputElement>(null);
+  const notifyNaggedEl = useRef<HTMLInputElement>(null);
+  const notifyMessagesEl = useRef<HTMLInputElement>(null);
+  const notifyMentionsEl = useRef<HTMLInputElement>(null);
 
   const mainContentRef = useRef<HTMLDivElement>(null);
   const [width, height] = useSize(mainContentRef);
 
   const [settings, setSettings] = useState<Settings | undefined>();
+  const [error, setError] = useState<CommandError>();
 
   useEffect(() => {
     (async () => {
@@ -29,7 +41,7 @@ const SettingsComponent = () => {
   }, [height, width]);
 
   useEffect(() => {
-    invoke<Settings>("fetch_settings").then(setSettings);
+    invoke<Settings>("fetch_settings").then(setSettings, setError);
   }, []);
 
   return (
@@ -58,6 +70,89 @@ const SettingsComponent = () => {
             </option>
           ))}
         </select>
+        <label for="server_url">Server URL</label>
+        <input
+          ref={serverUrlEl}
+          id="server_url"
+          type="text"
+          class="settings-server-url"
+          value={settings?.["serverUrl"]}
+        />
+        <label for="ca_bundle">CA Bundle (PEM)</label>
+        <input
+          ref={caBundleEl}
+          id="ca_bundle"
+          type="text"
+          class="settings-ca-bundle"
+          value={settings?.["caBundle"] ?? ""}
+        />
+        <label for="accept_invalid_certs">Accept Invalid Certificates</label>
+        <input
+          ref={acceptInvalidCertsEl}
+          id="accept_invalid_certs"
+          type="checkbox"
+          class="settings-accept-invalid-certs"
+          checked={settings?.["acceptInvalidCerts"]}
+        />
+        <label for="transport">Transport</label>
+        <select ref={transportEl} id="transport" class="settings-transport" value={settings?.["transport"] ?? ""}>
+          <option value="">Automatic</option>
+          {transports.map((transport) => (
+            <option key={transport} value={transport}>
+              {transport}
+            </option>
+          ))}
+        </select>
+        <label for="resync_interval">Relist Queues Every (Seconds, 0 For Never)</label>
+        <input
+          ref={resyncIntervalEl}
+          id="resync_interval"
+          type="number"
+          min={0}
+          class="settings-resync-interval"
+          value={settings?.["resyncIntervalSecs"] ?? 300}
+        />
+        <label for="notify_new_queue">Notify When A Queue Is Created</label>
+        <input
+          ref={notifyNewQueueEl}
+          id="notify_new_queue"
+          type="checkbox"
+          class="settings-notify-new-queue"
+          checked={settings?.["notifications"]?.["newQueue"] ?? true}
+        />
+        <label for="notify_status_changed">Notify When A Queue Is Started, Reset Or Closed</label>
+        <input
+          ref={notifyStatusChangedEl}
+          id="notify_status_changed"
+          type="checkbox"
+          class="settings-notify-status-changed"
+          checked={settings?.["notifications"]?.["statusChanged"] ?? true}
+        />
+        <label for="notify_nagged">Notify When Someone Nags A Queue</label>
+        <input
+          ref={notifyNaggedEl}
+          id="notify_nagged"
+          type="checkbox"
+          class="settings-notify-nagged"
+          checked={settings?.["notifications"]?.["nagged"] ?? true}
+        />
+        <label for="notify_messages">Notify When Someone Messages A Queue I'm In</label>
+        <input
+          ref={notifyMessagesEl}
+          id="notify_messages"
+          type="checkbox"
+          class="settings-notify-messages"
+          checked={settings?.["notifications"]?.["messages"] ?? true}
+        />
+        <label for="notify_mentions">Notify When Someone Mentions Me</label>
+        <input
+          ref={notifyMentionsEl}
+          id="notify_mentions"
+          type="checkbox"
+          class="settings-notify-mentions"
+          checked={settings?.["notifications"]?.["mentions"] ?? true}
+        />
+        {error && <div class="settings-error">{describeError(error)}</div>}
         <input
           type="submit"
           onClick={() => {
@@ -68,8 +163,22 @@ const SettingsComponent = () => {
                 email: emailEl.current?.value ?? "",
                 groups: (groupsEl.current?.value ?? "").trim().split(","),
                 theme: themeEl.current?.value ?? "Modern",
+                serverUrl: serverUrlEl.current?.value ?? "",
+                caBundle: caBundleEl.current?.value.trim() || null,
+                acceptInvalidCerts: acceptInvalidCertsEl.current?.checked ?? false,
+                transport: (transportEl.current?.value || null) as Transport | null,
+                resyncIntervalSecs: Math.max(0, Math.floor(Number(resyncIntervalEl.current?.value ?? 300) || 0)),
+                notifications: {
+                  newQueue: notifyNewQueueEl.current?.checked ?? true,
+                  statusChanged: notifyStatusChangedEl.current?.checked ?? true,
+                  nagged: notifyNaggedEl.current?.checked ?? true,
+                  messages: notifyMessagesEl.current?.checked ?? true,
+                  mentions: notifyMentionsEl.current?.checked ?? true,
+                },
+                // Rules are only edited in the settings file for now, so keep whatever is there
+                notificationRules: settings?.["notificationRules"] ?? [],
               },
-            });
+            }).then(() => setError(undefined), setError);
 
             console.log({
               fullName: fullNameEl.current?.value ?? "",
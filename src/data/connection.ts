@@ -0,0 +1,26 @@
+export type ConnectionState =
+  | { kind: "disconnected" }
+  | { kind: "negotiating" }
+  | { kind: "connected" }
+  | { kind: "reconnecting"; attempt: number; retryInMs: number };
+
+export interface ConnectionStatus {
+  state: ConnectionState;
+  lastError: string | null;
+  serverUrl: string | null;
+  sinceLastMessageMs: number | null;
+}
+
+export function describeConnection({ state, serverUrl }: ConnectionStatus): string {
+  const server = serverUrl ?? "server";
+  switch (state.kind) {
+    case "disconnected":
+      return "Disconnected";
+    case "negotiating":
+      return `Connecting to ${server}…`;
+    case "connected":
+      return `Connected to ${server}`;
+    case "reconnecting":
+      return `Reconnecting in ${Math.ceil(state.retryInMs / 1000)}s`;
+  }
+}
@@ -0,0 +1,50 @@
+import { Message } from "@/data/message";
+import { Queue } from "@/data/queue";
+import { User } from "@/data/user";
+
+export type QueueDiff =
+  | { kind: "added"; queue: Queue }
+  | { kind: "removed"; queue: Queue }
+  | { kind: "statusChanged"; id: number; from: Queue["status"]; to: Queue["status"] }
+  | { kind: "memberJoined"; id: number; user: User }
+  | { kind: "memberLeft"; id: number; user: User }
+  | { kind: "messageAppended"; id: number; message: Message }
+  | { kind: "replaced"; queue: Queue };
+
+function applyDiff(queues: Record<number, Queue>, diff: QueueDiff): Record<number, Queue> {
+  switch (diff.kind) {
+    case "added":
+    case "replaced":
+      return { ...queues, [diff.queue.id]: diff.queue };
+    case "removed": {
+      const { [diff.queue.id]: _removed, ...rest } = queues;
+      return rest;
+    }
+  }
+
+  const queue = queues[diff.id];
+  if (!queue) {
+    return queues;
+  }
+  switch (diff.kind) {
+    case "statusChanged":
+      return { ...queues, [diff.id]: { ...queue, status: diff.to } };
+    case "memberJoined":
+      return { ...queues, [diff.id]: { ...queue, members: [...queue.members, diff.user] as Queue["members"] } };
+    case "memberLeft":
+      return {
+        ...queues,
+        [diff.id]: {
+          ...queue,
+          members: queue.members.filter(({ username }) => username !== diff.user.username) as Queue["members"],
+        },
+      };
+    case "messageAppended":
+      return { ...queues, [diff.id]: { ...queue, messages: [...queue.messages, diff.message] as Queue["messages"] } };
+  }
+}
+
+/** Brings the queues up to date with the changes the backend sent */
+export function applyDiffs(queues: Record<number, Queue>, diffs: QueueDiff[]): Record<number, Queue> {
+  return diffs.reduce(applyDiff, queues);
+}
@@ -0,0 +1,28 @@
+/** Why a command failed, as returned by every Tauri command */
+export type CommandError =
+  | { kind: "notConnected" }
+  | { kind: "timeout" }
+  | { kind: "hubError"; message: string }
+  | { kind: "settings"; message: string }
+  | { kind: "cancelled" }
+  | { kind: "invalidInput"; message: string }
+  | { kind: "history"; message: string }
+  | { kind: "ui"; message: string };
+
+export function describeError(error: CommandError): string {
+  switch (error.kind) {
+    case "notConnected":
+      return "Not connected to the server";
+    case "timeout":
+      return "The server didn't respond in time";
+    case "hubError":
+      return `The server refused: ${error.message}`;
+    case "cancelled":
+      return "The connection was reset before the server responded";
+    case "settings":
+    case "invalidInput":
+    case "history":
+    case "ui":
+      return error.message;
+  }
+}
@@ -0,0 +1,28 @@
+export type HistoryEvent =
+  | { kind: "created" }
+  | { kind: "started" }
+  | { kind: "reset" }
+  | { kind: "closed" }
+  | { kind: "memberJoined" }
+  | { kind: "memberLeft" }
+  | { kind: "message"; content: string }
+  | { kind: "nagged" };
+
+export interface HistoryEntry {
+  /** Milliseconds since the Unix epoch */
+  at: number;
+  queueId: number;
+  queueName: string;
+  user: string | null;
+  event: HistoryEvent;
+}
+
+/** Arguments to the `fetch_history` command. Anything left out matches every entry. */
+export interface HistoryQuery {
+  queueId?: number;
+  user?: string;
+  since?: number;
+  until?: number;
+  /** At most this many entries, the latest ones */
+  limit?: number;
+}
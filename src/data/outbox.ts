@@ -0,0 +1,36 @@
+export type OutboxCommand =
+  | { kind: "joinQueue"; id: number }
+  | { kind: "leaveQueue"; id: number }
+  | { kind: "messageQueue"; id: number; content: string }
+  | { kind: "startQueue"; id: number }
+  | { kind: "resetQueue"; id: number }
+  | { kind: "nagQueue"; id: number }
+  | { kind: "deleteQueue"; id: number }
+  | { kind: "newQueue"; name: string; restrictToGroup: string | null };
+
+/** A command issued while disconnected, waiting to be sent */
+export interface PendingCommand {
+  id: number;
+  command: OutboxCommand;
+}
+
+export function describeCommand(command: OutboxCommand): string {
+  switch (command.kind) {
+    case "joinQueue":
+      return "Join";
+    case "leaveQueue":
+      return "Leave";
+    case "messageQueue":
+      return `Message "${command.content}"`;
+    case "startQueue":
+      return "Start";
+    case "resetQueue":
+      return "Reset";
+    case "nagQueue":
+      return "Nag";
+    case "deleteQueue":
+      return "Delete";
+    case "newQueue":
+      return `New queue "${command.name}"`;
+  }
+}
@@ -1,10 +1,44 @@
 export const themes = ["Win98", "ClassicQ3", "Modern"] as const;
 export type Theme = typeof themes[number];
 
+export const transports = ["webSockets", "serverSentEvents", "longPolling"] as const;
+export type Transport = typeof transports[number];
+
+export interface Notifications {
+  newQueue: boolean;
+  statusChanged: boolean;
+  nagged: boolean;
+  messages: boolean;
+  mentions: boolean;
+}
+
+export const eventKinds = ["newQueue", "statusChanged", "membershipChanged", "nagged", "message"] as const;
+export type EventKind = typeof eventKinds[number];
+
+export const notificationActions = ["popup", "notification", "sound"] as const;
+export type NotificationAction = typeof notificationActions[number];
+
+export interface NotificationRule {
+  queueName: string | null;
+  group: string | null;
+  memberOnly: boolean;
+  event: EventKind | null;
+  sender: string | null;
+  mentioned: boolean;
+  actions: NotificationAction[];
+}
+
 export interface Settings {
   fullName: string;
   username: string;
   email: string;
   groups: [string];
   theme: Theme;
+  serverUrl: string;
+  caBundle: string | null;
+  acceptInvalidCerts: boolean;
+  transport: Transport | null;
+  resyncIntervalSecs: number;
+  notifications: Notifications;
+  notificationRules: NotificationRule[];
 }
@@ -40,3 +40,20 @@ export const themeCssMap: Record<Theme, string> = {
   ClassicQ3: classicQ3Css,
   Modern: "",
 } as const;
+
+/** Plays a short two-note chime, for notification rules that ask for a sound */
+export function playChime() {
+  const context = new AudioContext();
+  [880, 1320].forEach((frequency, i) => {
+    const oscillator = context.createOscillator();
+    const gain = context.createGain();
+    const start = context.currentTime + i * 0.15;
+    oscillator.frequency.value = frequency;
+    gain.gain.setValueAtTime(0.2, start);
+    gain.gain.exponentialRampToValueAtTime(0.001, start + 0.3);
+    oscillator.connect(gain).connect(context.destination);
+    oscillator.start(start);
+    oscillator.stop(start + 0.3);
+  });
+  setTimeout(() => context.close(), 1000);
+}
@@ -0,0 +1,50 @@
+//! Fixtures shared by the integration tests. Each test binary only uses some of them.
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use rq::{Queue, QueueStatus, User};
+
+pub fn user(username: &str) -> User {
+    User {
+        username: username.to_owned(),
+        full_name: format!("{} Example", username),
+        email: format!("{}@example.com", username),
+    }
+}
+
+pub fn queue(name: &str, group: &str, members: &[&str]) -> Queue {
+    Queue {
+        id: 1,
+        name: name.to_owned(),
+        status: QueueStatus::Open,
+        members: members.iter().map(|member| user(member)).collect(),
+        messages: Vec::new(),
+        restrict_to_group: group.to_owned(),
+    }
+}
+
+/// A fresh directory for a test to keep its files in, removed again afterwards
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir()
+            .join(format!("rq-test-{}", std::process::id()))
+            .join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
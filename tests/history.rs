@@ -0,0 +1,210 @@
+//! Recording what happens to queues and looking it up again.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rq::{
+    ClientEvent, History, HistoryEntry, HistoryEvent, HistoryQuery, Message, Queue, QueueChange,
+    QueueDiff, QueueStatus,
+};
+
+mod common;
+
+use common::{queue, user, TempDir};
+
+fn lunch() -> Queue {
+    queue("Lunch", "", &["alice"])
+}
+
+/// A history in a directory of its own, removed again afterwards
+struct TempHistory {
+    history: History,
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl TempHistory {
+    async fn open(name: &str) -> Self {
+        let dir = TempDir::new(name);
+        let path = dir.path().join("history.jsonl");
+
+        Self {
+            history: History::open(&path).await.unwrap(),
+            path,
+            _dir: dir,
+        }
+    }
+
+    /// Records a busy afternoon in the lunch queue
+    async fn record_lunch(&self) {
+        let queues = BTreeMap::from([(1, lunch())]);
+        let events = [
+            ClientEvent::QueueChanged {
+                change: QueueChange::Created,
+                queue: lunch(),
+            },
+            ClientEvent::QueuesDiffed(vec![QueueDiff::MemberJoined {
+                id: 1,
+                user: user("bob"),
+            }]),
+            ClientEvent::QueuesDiffed(vec![
+                QueueDiff::StatusChanged {
+                    id: 1,
+                    from: QueueStatus::Open,
+                    to: QueueStatus::Started,
+                },
+                QueueDiff::MessageAppended {
+                    id: 1,
+                    message: Message {
+                        content: "Pizza?".to_owned(),
+                        sender: user("bob"),
+                    },
+                },
+            ]),
+            ClientEvent::Nagged {
+                queue: lunch(),
+                by: Some(user("alice")),
+            },
+            // Nothing to record
+            ClientEvent::QueuesListed(queues.clone()),
+        ];
+
+        for event in &events {
+            self.history.record(event, &queues).await.unwrap();
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[tokio::test]
+async fn records_everything_that_happens() {
+    let test = TempHistory::open("everything").await;
+    test.record_lunch().await;
+
+    let entries = test.history.query(&HistoryQuery::default()).await.unwrap();
+
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| (entry.user.as_deref(), entry.event.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (Some("alice"), HistoryEvent::Created),
+            (Some("bob"), HistoryEvent::MemberJoined),
+            (None, HistoryEvent::Started),
+            (
+                Some("bob"),
+                HistoryEvent::Message {
+                    content: "Pizza?".to_owned()
+                }
+            ),
+            (Some("alice"), HistoryEvent::Nagged),
+        ]
+    );
+    assert!(entries
+        .iter()
+        .all(|entry| entry.queue_id == 1 && entry.queue_name == "Lunch"));
+}
+
+#[tokio::test]
+async fn history_survives_reopening() {
+    let test = TempHistory::open("reopening").await;
+    test.record_lunch().await;
+
+    let reopened = History::open(&test.path).await.unwrap();
+
+    assert_eq!(
+        reopened
+            .query(&HistoryQuery::default())
+            .await
+            .unwrap()
+            .len(),
+        5
+    );
+}
+
+#[tokio::test]
+async fn queries_filter_by_queue_user_and_time() {
+    let test = TempHistory::open("filtering").await;
+    let before = now();
+    test.record_lunch().await;
+    let after = now();
+
+    let by_bob = test
+        .history
+        .query(&HistoryQuery {
+            user: Some("bob".to_owned()),
+            ..HistoryQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(by_bob.len(), 2);
+
+    let other_queue = HistoryQuery {
+        queue_id: Some(2),
+        ..HistoryQuery::default()
+    };
+    assert!(test.history.query(&other_queue).await.unwrap().is_empty());
+
+    let during = HistoryQuery {
+        since: Some(before),
+        until: Some(after),
+        ..HistoryQuery::default()
+    };
+    assert_eq!(test.history.query(&during).await.unwrap().len(), 5);
+
+    let later = HistoryQuery {
+        since: Some(after + 1),
+        ..HistoryQuery::default()
+    };
+    assert!(test.history.query(&later).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn queries_can_be_limited_to_the_latest_entries() {
+    let test = TempHistory::open("limiting").await;
+    let queues = BTreeMap::from([(1, lunch())]);
+    // Long enough that the log has to be read in several pieces
+    for i in 0..1000 {
+        let event = ClientEvent::QueuesDiffed(vec![QueueDiff::MessageAppended {
+            id: 1,
+            message: Message {
+                content: format!("{} {}", i, "Pizza? ".repeat(20)),
+                sender: user("bob"),
+            },
+        }]);
+        test.history.record(&event, &queues).await.unwrap();
+    }
+
+    let contents = |entries: Vec<HistoryEntry>| {
+        entries
+            .into_iter()
+            .map(|entry| match entry.event {
+                HistoryEvent::Message { content } => content.split(' ').next().unwrap().to_owned(),
+                event => panic!("Unexpected {:?}", event),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let latest = HistoryQuery {
+        limit: Some(3),
+        ..HistoryQuery::default()
+    };
+    assert_eq!(
+        contents(test.history.query(&latest).await.unwrap()),
+        ["997", "998", "999"]
+    );
+
+    let everything = test.history.query(&HistoryQuery::default()).await.unwrap();
+    assert_eq!(
+        contents(everything),
+        (0..1000).map(|i| i.to_string()).collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,684 @@
+//! Drives [`QHubClient`] against [`MockQHub`]: connecting, invoking every hub method, and keeping
+//! up with the notifications the server pushes.
+
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, timeout};
+
+use rq::{
+    ClientEvent, CommandError, ConnectionState, OutboxCommand, QHubClient, Queue, QueueChange,
+    QueueDiff, QueueStatus, Settings, TransportKind,
+};
+
+mod common;
+mod mock_qhub;
+
+use common::TempDir;
+use mock_qhub::MockQHub;
+
+/// How long a test waits for something that should happen straight away
+const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running client along with the events it has emitted since it was spawned
+struct TestClient {
+    client: QHubClient,
+    events: broadcast::Receiver<ClientEvent>,
+}
+
+impl TestClient {
+    async fn spawn(hub: &MockQHub, username: &str) -> Self {
+        Self::spawn_with(hub.settings(username))
+    }
+
+    fn spawn_with(settings: Settings) -> Self {
+        Self::start(QHubClient::new(settings))
+    }
+
+    fn start(client: QHubClient) -> Self {
+        let events = client.subscribe();
+        client.spawn();
+
+        Self { client, events }
+    }
+
+    /// Spawns a client and waits until it's connected and has listed the queues
+    async fn connect(hub: &MockQHub, username: &str) -> Self {
+        Self::connect_with(hub.settings(username)).await
+    }
+
+    async fn connect_with(settings: Settings) -> Self {
+        let mut client = Self::spawn_with(settings);
+        client.wait_until_listed().await;
+        client
+    }
+
+    async fn wait_until_listed(&mut self) {
+        self.wait_for_state(ConnectionState::Connected).await;
+        self.next_event(|event| matches!(event, ClientEvent::QueuesListed(_)))
+            .await;
+    }
+
+    /// Skips events until one matches
+    async fn next_event(&mut self, mut wanted: impl FnMut(&ClientEvent) -> bool) -> ClientEvent {
+        timeout(TEST_TIMEOUT, async {
+            loop {
+                let event = self.events.recv().await.expect("Client stopped emitting");
+                if wanted(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for an event")
+    }
+
+    async fn wait_for_state(&mut self, state: ConnectionState) {
+        self.next_event(
+            |event| matches!(event, ClientEvent::ConnectionStatus(status) if status.state == state),
+        )
+        .await;
+    }
+
+    /// Waits for the server to announce a change to a queue
+    async fn next_queue_change(&mut self) -> (QueueChange, Queue) {
+        match self
+            .next_event(|event| matches!(event, ClientEvent::QueueChanged { .. }))
+            .await
+        {
+            ClientEvent::QueueChanged { change, queue } => (change, queue),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn next_diffs(&mut self) -> Vec<QueueDiff> {
+        match self
+            .next_event(|event| matches!(event, ClientEvent::QueuesDiffed(_)))
+            .await
+        {
+            ClientEvent::QueuesDiffed(diffs) => diffs,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn lists_existing_queues() {
+    let hub = MockQHub::start().await;
+    let lunch = hub.add_queue("Lunch", QueueStatus::Open);
+    let review = hub.add_queue("Code review", QueueStatus::Started);
+
+    let client = TestClient::connect(&hub, "alice").await.client;
+
+    assert_eq!(
+        client
+            .queues()
+            .await
+            .values()
+            .map(|queue| (queue.id, queue.name.clone(), queue.status))
+            .collect::<Vec<_>>(),
+        vec![
+            (lunch, "Lunch".to_owned(), QueueStatus::Open),
+            (review, "Code review".to_owned(), QueueStatus::Started),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn creating_a_queue_announces_it() {
+    let hub = MockQHub::start().await;
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    test.client
+        .new_queue("Lunch", Some("developers"))
+        .await
+        .expect("Couldn't create queue");
+    let (change, queue) = test.next_queue_change().await;
+
+    assert_eq!(change, QueueChange::Created);
+    assert_eq!(queue.name, "Lunch");
+    assert_eq!(queue.status, QueueStatus::Open);
+    assert_eq!(queue.restrict_to_group, "developers");
+    assert_eq!(queue.members[0].username, "alice");
+    assert_eq!(queue.members[0].email, "alice@example.com");
+    assert_eq!(test.client.queues().await.get(&queue.id), Some(&queue));
+}
+
+#[tokio::test]
+async fn joining_and_leaving_change_membership() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    test.client.join_queue(id).await.unwrap();
+    let (change, joined) = test.next_queue_change().await;
+    assert_eq!(change, QueueChange::MembershipChanged);
+    assert_eq!(joined.members.len(), 1);
+    assert_eq!(joined.members[0].full_name, "alice Example");
+
+    test.client.leave_queue(id).await.unwrap();
+    let (_, left) = test.next_queue_change().await;
+    assert!(left.members.is_empty());
+    assert!(test.client.queues().await[&id].members.is_empty());
+}
+
+#[tokio::test]
+async fn starting_resetting_and_closing_change_status() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    test.client.start_queue(id).await.unwrap();
+    assert_eq!(
+        test.next_queue_change().await.1.status,
+        QueueStatus::Started
+    );
+
+    test.client.reset_queue(id).await.unwrap();
+    assert_eq!(test.next_queue_change().await.1.status, QueueStatus::Open);
+
+    test.client.delete_queue(id).await.unwrap();
+    let (change, queue) = test.next_queue_change().await;
+    assert_eq!(change, QueueChange::StatusChanged);
+    assert_eq!(queue.status, QueueStatus::Closed);
+    assert!(hub.queue(id).is_none());
+}
+
+#[tokio::test]
+async fn messages_reach_the_queue() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut alice = TestClient::connect(&hub, "alice").await;
+    let mut bob = TestClient::connect(&hub, "bob").await;
+
+    alice.client.message_queue(id, "Pizza?").await.unwrap();
+
+    let queue = hub.queue(id).unwrap();
+    assert_eq!(queue["Messages"][0]["Content"], "Pizza?");
+    assert_eq!(queue["Messages"][0]["Sender"]["UserName"], "alice");
+
+    for test in [&mut alice, &mut bob] {
+        let ClientEvent::MessageSent { queue, message } = test
+            .next_event(|event| matches!(event, ClientEvent::MessageSent { .. }))
+            .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(queue.id, id);
+        assert_eq!(message.content, "Pizza?");
+        assert_eq!(message.sender.username, "alice");
+        assert_eq!(test.client.queues().await[&id].messages, vec![message]);
+    }
+}
+
+#[tokio::test]
+async fn changes_are_reported_as_diffs() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    test.client.join_queue(id).await.unwrap();
+    let [QueueDiff::MemberJoined { id: joined, user }] = &test.next_diffs().await[..] else {
+        panic!("Expected only a member to join");
+    };
+    assert_eq!((*joined, user.username.as_str()), (id, "alice"));
+
+    test.client.start_queue(id).await.unwrap();
+    assert_eq!(
+        test.next_diffs().await,
+        vec![QueueDiff::StatusChanged {
+            id,
+            from: QueueStatus::Open,
+            to: QueueStatus::Started
+        }]
+    );
+
+    test.client.message_queue(id, "Pizza?").await.unwrap();
+    let [QueueDiff::MessageAppended { message, .. }] = &test.next_diffs().await[..] else {
+        panic!("Expected only a message to be appended");
+    };
+    assert_eq!(message.content, "Pizza?");
+}
+
+#[tokio::test]
+async fn relisting_only_reports_what_changed() {
+    let hub = MockQHub::start().await;
+    hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect_with(Settings {
+        resync_interval_secs: 1,
+        ..hub.settings("alice")
+    })
+    .await;
+
+    // Added behind the client's back, so it only finds out when it lists the queues again
+    let id = hub.add_queue("Code review", QueueStatus::Open);
+
+    let [QueueDiff::Added { queue }] = &test.next_diffs().await[..] else {
+        panic!("Expected only a queue to be added");
+    };
+    assert_eq!(queue.id, id);
+}
+
+#[tokio::test]
+async fn malformed_notifications_cause_a_resync() {
+    let hub = MockQHub::start().await;
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    hub.notify("QueueStatusChanged", json!(["Not a queue"]));
+
+    test.next_event(|event| matches!(event, ClientEvent::QueuesListed(_)))
+        .await;
+}
+
+#[tokio::test]
+async fn notifications_about_unknown_queues_cause_a_resync() {
+    let hub = MockQHub::start().await;
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    // Added without announcing it, so the client missed its creation
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    hub.invoke_as("bob", "JoinQueue", json!([id]));
+
+    test.next_event(|event| matches!(event, ClientEvent::QueuesListed(_)))
+        .await;
+}
+
+#[tokio::test]
+async fn starts_from_the_queues_cached_last_time() {
+    let hub = MockQHub::start().await;
+    let lunch = hub.add_queue("Lunch", QueueStatus::Open);
+    let dir = TempDir::new("cache");
+
+    let mut first = TestClient::start(QHubClient::with_data_dir(hub.settings("alice"), dir.path()));
+    assert!(!first.client.queues_stale());
+    first.wait_until_listed().await;
+    first.client.shutdown().await;
+    let cached = first.client.queues().await;
+
+    // Straight away, before connecting
+    let second = QHubClient::with_data_dir(hub.settings("alice"), dir.path());
+    assert_eq!(second.queues().await, cached);
+    assert!(second.queues_stale());
+
+    let review = hub.add_queue("Code review", QueueStatus::Started);
+    let mut second = TestClient::start(second);
+    second.wait_until_listed().await;
+
+    assert!(!second.client.queues_stale());
+    assert_eq!(
+        second
+            .client
+            .queues()
+            .await
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![lunch, review]
+    );
+}
+
+#[tokio::test]
+async fn commands_issued_while_disconnected_are_sent_once_connected() {
+    let hub = MockQHub::start().await;
+    let lunch = hub.add_queue("Lunch", QueueStatus::Open);
+    let client = QHubClient::new(hub.settings("alice"));
+
+    for command in [
+        OutboxCommand::JoinQueue { id: lunch },
+        OutboxCommand::MessageQueue {
+            id: lunch,
+            content: "Pizza?".to_owned(),
+        },
+    ] {
+        client.run_or_queue(command).await.unwrap();
+    }
+    assert_eq!(client.outbox().await.len(), 2);
+
+    let mut test = TestClient::start(client);
+    // Sent in order, so alice had joined by the time she asked
+    match test
+        .next_event(|event| matches!(event, ClientEvent::MessageSent { .. }))
+        .await
+    {
+        ClientEvent::MessageSent { queue, message } => {
+            assert_eq!(message.content, "Pizza?");
+            assert_eq!(queue.members[0].username, "alice");
+        }
+        _ => unreachable!(),
+    }
+    test.next_event(
+        |event| matches!(event, ClientEvent::OutboxChanged(commands) if commands.is_empty()),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn commands_issued_during_replay_wait_their_turn() {
+    let hub = MockQHub::start().await;
+    let lunch = hub.add_queue("Lunch", QueueStatus::Open);
+    let client = QHubClient::new(hub.settings("alice"));
+
+    for command in [
+        OutboxCommand::JoinQueue { id: lunch },
+        OutboxCommand::MessageQueue {
+            id: lunch,
+            content: "Pizza?".to_owned(),
+        },
+    ] {
+        client.run_or_queue(command).await.unwrap();
+    }
+
+    let mut test = TestClient::start(client);
+    test.wait_for_state(ConnectionState::Connected).await;
+    // Issued while the outbox is still being sent, so they have to go after it
+    for command in [
+        OutboxCommand::MessageQueue {
+            id: lunch,
+            content: "Sushi?".to_owned(),
+        },
+        OutboxCommand::LeaveQueue { id: lunch },
+    ] {
+        test.client.run_or_queue(command).await.unwrap();
+    }
+    test.next_event(
+        |event| matches!(event, ClientEvent::OutboxChanged(commands) if commands.is_empty()),
+    )
+    .await;
+
+    let queue = hub.queue(lunch).unwrap();
+    assert_eq!(queue["Messages"][0]["Content"], "Pizza?");
+    assert_eq!(queue["Messages"][1]["Content"], "Sushi?");
+    assert_eq!(queue["Members"], json!([]));
+}
+
+#[tokio::test]
+async fn the_outbox_drops_conflicting_commands_and_survives_restarts() {
+    let dir = TempDir::new("outbox");
+    let client = QHubClient::with_data_dir(Settings::default(), dir.path());
+
+    for command in [
+        OutboxCommand::JoinQueue { id: 1 },
+        OutboxCommand::JoinQueue { id: 1 },
+        OutboxCommand::NagQueue { id: 2 },
+        OutboxCommand::LeaveQueue { id: 1 },
+        OutboxCommand::StartQueue { id: 3 },
+        OutboxCommand::ResetQueue { id: 3 },
+        OutboxCommand::StartQueue { id: 3 },
+        OutboxCommand::DeleteQueue { id: 2 },
+    ] {
+        client.run_or_queue(command).await.unwrap();
+    }
+
+    let restarted = QHubClient::with_data_dir(Settings::default(), dir.path());
+    assert_eq!(
+        restarted
+            .outbox()
+            .await
+            .into_iter()
+            .map(|pending| pending.command)
+            .collect::<Vec<_>>(),
+        vec![
+            OutboxCommand::StartQueue { id: 3 },
+            OutboxCommand::ResetQueue { id: 3 },
+            OutboxCommand::StartQueue { id: 3 },
+            OutboxCommand::DeleteQueue { id: 2 },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn nagging_notifies_everyone() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Started);
+    let mut alice = TestClient::connect(&hub, "alice").await;
+    let mut bob = TestClient::connect(&hub, "bob").await;
+
+    alice.client.nag_queue(id).await.unwrap();
+
+    for test in [&mut alice, &mut bob] {
+        let ClientEvent::Nagged { queue, by } = test
+            .next_event(|event| matches!(event, ClientEvent::Nagged { .. }))
+            .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(queue.id, id);
+        assert_eq!(by.unwrap().username, "alice");
+    }
+}
+
+#[tokio::test]
+async fn invocations_return_typed_results() {
+    let hub = MockQHub::start().await;
+    let lunch = hub.add_queue("Lunch", QueueStatus::Open);
+    let test = TestClient::connect(&hub, "alice").await;
+
+    let queues: Vec<serde_json::Value> = test
+        .client
+        .invoke("ListQueues", [(); 0])
+        .await
+        .expect("Couldn't list queues");
+
+    assert_eq!(queues.len(), 1);
+    assert_eq!(queues[0]["Id"], json!(lunch));
+}
+
+#[tokio::test]
+async fn concurrent_invocations_each_get_their_own_result() {
+    let hub = MockQHub::start().await;
+    let ids: Vec<u64> = (0..10)
+        .map(|n| hub.add_queue(&format!("Queue {}", n), QueueStatus::Open))
+        .collect();
+    let test = TestClient::connect(&hub, "alice").await;
+
+    let joins: Vec<_> = ids
+        .iter()
+        .map(|&id| {
+            let client = test.client.clone();
+            tokio::spawn(async move { client.join_queue(id).await })
+        })
+        .collect();
+    for join in joins {
+        join.await.unwrap().expect("Couldn't join queue");
+    }
+
+    // Straight from the server, as the notifications may not all have arrived yet
+    let queues: Vec<serde_json::Value> = test.client.invoke("ListQueues", [(); 0]).await.unwrap();
+    assert_eq!(queues.len(), ids.len());
+    assert!(queues
+        .iter()
+        .all(|queue| queue["Members"][0]["UserName"] == "alice"));
+}
+
+#[tokio::test]
+async fn hub_errors_are_returned_to_the_caller() {
+    let hub = MockQHub::start().await;
+    let test = TestClient::connect(&hub, "alice").await;
+
+    let error = test.client.join_queue(42).await.unwrap_err();
+
+    assert_eq!(error.to_string(), "Hub error: Queue 42 does not exist");
+    // Frontends get to tell the server refusing from anything else going wrong
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({ "kind": "hubError", "message": "Queue 42 does not exist" })
+    );
+}
+
+#[tokio::test]
+async fn commands_fail_while_disconnected() {
+    let hub = MockQHub::start().await;
+    let client = QHubClient::new(hub.settings("alice"));
+
+    let error = client.join_queue(1).await.unwrap_err();
+
+    assert_eq!(error, CommandError::NotConnected);
+    assert_eq!(error.to_string(), "Not connected to the server");
+}
+
+#[tokio::test]
+async fn server_sent_events_carry_invocations_and_notifications() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect_with(Settings {
+        transport: Some(TransportKind::ServerSentEvents),
+        ..hub.settings("alice")
+    })
+    .await;
+
+    test.client.join_queue(id).await.unwrap();
+    let (_, joined) = test.next_queue_change().await;
+    assert_eq!(joined.members[0].username, "alice");
+
+    // Replies to sent messages come back in the response to the send, not over the stream
+    let error = test.client.join_queue(42).await.unwrap_err();
+    assert_eq!(error.to_string(), "Hub error: Queue 42 does not exist");
+    assert_eq!(
+        hub.connected_transports(),
+        vec![TransportKind::ServerSentEvents]
+    );
+}
+
+#[tokio::test]
+async fn long_polling_carries_invocations_and_notifications() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let delay = Duration::from_millis(200);
+    hub.set_long_poll_delay(delay);
+    let mut test = TestClient::connect_with(Settings {
+        transport: Some(TransportKind::LongPolling),
+        ..hub.settings("alice")
+    })
+    .await;
+
+    test.client.join_queue(id).await.unwrap();
+    test.next_queue_change().await;
+    // Both land while the client waits to poll again, so it has to resume from where it got to
+    hub.invoke_as("bob", "JoinQueue", json!([id]));
+    hub.invoke_as("carol", "JoinQueue", json!([id]));
+
+    test.next_queue_change().await;
+    let (_, queue) = test.next_queue_change().await;
+    assert_eq!(
+        queue
+            .members
+            .iter()
+            .map(|member| member.username.as_str())
+            .collect::<Vec<_>>(),
+        vec!["alice", "bob", "carol"]
+    );
+    assert_eq!(hub.connected_transports(), vec![TransportKind::LongPolling]);
+
+    let polls = hub.long_polls();
+    assert!(polls.len() >= 3, "Only polled {} times", polls.len());
+    for pair in polls.windows(2) {
+        assert!(pair[1] - pair[0] >= delay, "Polled again too soon");
+    }
+}
+
+#[tokio::test]
+async fn falls_back_when_websockets_are_refused() {
+    let hub = MockQHub::start().await;
+    hub.refuse_transport(TransportKind::WebSockets);
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect_with(Settings {
+        transport: None,
+        ..hub.settings("alice")
+    })
+    .await;
+
+    test.client.join_queue(id).await.unwrap();
+    test.next_queue_change().await;
+
+    assert_eq!(
+        hub.connected_transports(),
+        vec![TransportKind::ServerSentEvents]
+    );
+}
+
+#[tokio::test]
+async fn invalid_settings_are_refused() {
+    let hub = MockQHub::start().await;
+    let client = QHubClient::new(hub.settings("alice"));
+
+    let error = client
+        .update_settings(Settings {
+            server_url: "ftp://example.com".to_owned(),
+            ..hub.settings("alice")
+        })
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(error, CommandError::InvalidInput { .. }),
+        "{:?}",
+        error
+    );
+    assert_eq!(client.settings().await.server_url, hub.server_url());
+}
+
+#[tokio::test]
+async fn resuming_delivers_missed_notifications() {
+    let hub = MockQHub::start().await;
+    let id = hub.add_queue("Lunch", QueueStatus::Open);
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    hub.drop_connections();
+    test.wait_for_state(ConnectionState::Disconnected).await;
+    hub.invoke_as("bob", "ActivateQueue", json!([id]));
+
+    let (change, queue) = test.next_queue_change().await;
+    assert_eq!(change, QueueChange::StatusChanged);
+    assert_eq!(queue.status, QueueStatus::Started);
+}
+
+#[tokio::test]
+async fn shutting_down_aborts_the_connection() {
+    let hub = MockQHub::start().await;
+    let test = TestClient::connect(&hub, "alice").await;
+
+    test.client.shutdown().await;
+
+    assert_eq!(hub.aborted().len(), 1);
+}
+
+#[tokio::test]
+async fn pings_keep_the_connection_alive() {
+    let hub = MockQHub::start().await;
+    hub.set_keep_alive_timeout(0.3);
+    let mut test = TestClient::connect(&hub, "alice").await;
+
+    sleep(Duration::from_secs(1)).await;
+
+    assert!(hub.pings() >= 3, "Only pinged {} times", hub.pings());
+    assert_eq!(
+        test.client.connection_status().await.state,
+        ConnectionState::Connected
+    );
+    // Notifications keep the queues up to date, so pinging doesn't list them again
+    while let Ok(event) = test.events.try_recv() {
+        assert!(
+            !matches!(event, ClientEvent::QueuesListed(_)),
+            "Listed the queues again"
+        );
+    }
+}
+
+#[tokio::test]
+async fn refuses_unsupported_protocol_versions() {
+    let hub = MockQHub::start().await;
+    hub.set_protocol_version("1.3");
+    let mut test = TestClient::spawn(&hub, "alice").await;
+
+    test.next_event(|event| match event {
+        ClientEvent::ConnectionStatus(status) => {
+            matches!(status.state, ConnectionState::Reconnecting { .. })
+        }
+        _ => false,
+    })
+    .await;
+
+    let error = test.client.connection_status().await.last_error.unwrap();
+    assert!(error.contains("protocol version 1.3"), "{}", error);
+}
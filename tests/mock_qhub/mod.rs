@@ -0,0 +1,682 @@
+//! An in-process stand-in for the QHub server, speaking just enough of the SignalR 2.x persistent
+//! connection protocol, over any of its transports, for the client to negotiate, connect, resume,
+//! invoke every hub method and receive the notifications those invocations cause.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::body::{Bytes, Sender as BodySender};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message as WebsocketMessage;
+use tokio_tungstenite::WebSocketStream;
+
+use rq::{QueueStatus, Settings, TransportKind};
+
+const HUB_NAME: &str = "QHub";
+const PROTOCOL_VERSION: &str = "1.4";
+/// How long a poll is held when there's nothing to send, kept short so tests don't hang on one
+const POLL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Something every open connection needs to hear about
+#[derive(Debug, Clone)]
+enum Event {
+    /// A hub message, with the cursor the client resumes from once it has seen it
+    Notification {
+        cursor: u64,
+        message: Value,
+    },
+    Aborted {
+        connection_token: String,
+    },
+    /// Every connection is dropped, as if the network went away
+    DropConnections,
+}
+
+#[derive(Debug, Clone)]
+struct MockUser {
+    username: String,
+    full_name: String,
+    email: String,
+}
+
+impl MockUser {
+    /// Parses the `User` header the client sends with every request
+    fn from_header(header: &str) -> Self {
+        let mut parts = header.splitn(3, ';');
+        let mut next = || parts.next().unwrap_or_default().to_owned();
+
+        Self {
+            username: next(),
+            full_name: next(),
+            email: next(),
+        }
+    }
+
+    fn to_wire(&self) -> Value {
+        json!({
+            "UserName": self.username,
+            "FullName": self.full_name,
+            "EmailAddress": self.email,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MockQueue {
+    id: u64,
+    name: String,
+    status: QueueStatus,
+    members: Vec<MockUser>,
+    messages: Vec<(String, MockUser)>,
+    restrict_to_group: String,
+}
+
+impl MockQueue {
+    fn to_wire(&self) -> Value {
+        json!({
+            "Id": self.id,
+            "Name": self.name,
+            "Status": self.status as u64,
+            "Members": self.members.iter().map(MockUser::to_wire).collect::<Vec<_>>(),
+            "Messages": self
+                .messages
+                .iter()
+                .map(|(content, sender)| json!({ "Content": content, "Sender": sender.to_wire() }))
+                .collect::<Vec<_>>(),
+            "RestrictToGroup": self.restrict_to_group,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Hub {
+    protocol_version: String,
+    keep_alive_timeout: f64,
+    queues: BTreeMap<u64, MockQueue>,
+    next_queue_id: u64,
+    /// Every notification sent so far, so resumed connections can be sent what they missed
+    log: Vec<Value>,
+    connection_tokens: HashSet<String>,
+    aborted: Vec<String>,
+    pings: usize,
+    /// Transports that are turned away, as if a proxy got in the way
+    refused: HashSet<TransportKind>,
+    /// Transport of every connection opened or resumed
+    connected: Vec<TransportKind>,
+    /// Milliseconds long-polling clients are told to wait between polls
+    long_poll_delay: u64,
+    /// When each long poll arrived, the one that connected included
+    polls: Vec<Instant>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Hub {
+    fn notify(&mut self, method: &str, args: Value) {
+        let message = json!({ "H": HUB_NAME, "M": method, "A": args });
+        self.log.push(message.clone());
+        let _ = self.events.send(Event::Notification {
+            cursor: self.log.len() as u64,
+            message,
+        });
+    }
+
+    fn queue_mut(&mut self, id: &Value) -> Result<&mut MockQueue, String> {
+        let id = id.as_u64().ok_or("Queue id must be a number")?;
+        self.queues
+            .get_mut(&id)
+            .ok_or_else(|| format!("Queue {} does not exist", id))
+    }
+
+    /// Runs a hub method the way QHub would, returning its result or the error message it raises
+    fn invoke(&mut self, user: &MockUser, method: &str, args: &[Value]) -> Result<Value, String> {
+        let arg = |index: usize| args.get(index).cloned().unwrap_or(Value::Null);
+
+        match method {
+            "ListQueues" => Ok(self.queues.values().map(MockQueue::to_wire).collect()),
+            "StartQueue" => {
+                let name = arg(0)
+                    .as_str()
+                    .ok_or("Queue name must be a string")?
+                    .to_owned();
+                let queue = MockQueue {
+                    id: self.next_queue_id,
+                    name,
+                    status: QueueStatus::Open,
+                    members: vec![user.clone()],
+                    messages: Vec::new(),
+                    restrict_to_group: arg(1).as_str().unwrap_or_default().to_owned(),
+                };
+                self.next_queue_id += 1;
+                let wire = queue.to_wire();
+                self.queues.insert(queue.id, queue);
+                self.notify("NewQueue", json!([wire]));
+                Ok(Value::Null)
+            }
+            "JoinQueue" | "LeaveQueue" => {
+                let queue = self.queue_mut(&arg(0))?;
+                queue
+                    .members
+                    .retain(|member| member.username != user.username);
+                if method == "JoinQueue" {
+                    queue.members.push(user.clone());
+                }
+                let wire = queue.to_wire();
+                self.notify("QueueMembershipChanged", json!([wire]));
+                Ok(Value::Null)
+            }
+            "ActivateQueue" | "DeactivateQueue" | "CloseQueue" => {
+                let queue = self.queue_mut(&arg(0))?;
+                queue.status = match method {
+                    "ActivateQueue" => QueueStatus::Started,
+                    "DeactivateQueue" => QueueStatus::Open,
+                    _ => QueueStatus::Closed,
+                };
+                let wire = queue.to_wire();
+                if method == "CloseQueue" {
+                    let id = queue.id;
+                    self.queues.remove(&id);
+                }
+                self.notify("QueueStatusChanged", json!([wire]));
+                Ok(Value::Null)
+            }
+            "MessageQueue" => {
+                let content = arg(1)
+                    .as_str()
+                    .ok_or("Message must be a string")?
+                    .to_owned();
+                let queue = self.queue_mut(&arg(0))?;
+                queue.messages.push((content.clone(), user.clone()));
+                let id = queue.id;
+                self.notify(
+                    "QueueMessageSent",
+                    json!([id, { "Content": content, "Sender": user.to_wire() }]),
+                );
+                Ok(Value::Null)
+            }
+            "NagQueue" => {
+                let wire = self.queue_mut(&arg(0))?.to_wire();
+                self.notify("NagQueue", json!([wire, user.to_wire()]));
+                Ok(Value::Null)
+            }
+            _ => Err(format!("'{}' method could not be resolved", method)),
+        }
+    }
+}
+
+/// A running mock server. It lives until the test's runtime shuts down.
+#[derive(Debug, Clone)]
+pub struct MockQHub {
+    address: SocketAddr,
+    hub: Arc<Mutex<Hub>>,
+}
+
+impl MockQHub {
+    pub async fn start() -> Self {
+        let (events, _) = broadcast::channel(64);
+        let hub = Arc::new(Mutex::new(Hub {
+            protocol_version: PROTOCOL_VERSION.to_owned(),
+            keep_alive_timeout: 20.0,
+            queues: BTreeMap::new(),
+            next_queue_id: 1,
+            log: Vec::new(),
+            connection_tokens: HashSet::new(),
+            aborted: Vec::new(),
+            pings: 0,
+            refused: HashSet::new(),
+            connected: Vec::new(),
+            long_poll_delay: 0,
+            polls: Vec::new(),
+            events,
+        }));
+
+        let service_hub = hub.clone();
+        let make_service = make_service_fn(move |_| {
+            let hub = service_hub.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let hub = hub.clone();
+                    async move { Ok::<_, Infallible>(handle(hub, request).await) }
+                }))
+            }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        Self { address, hub }
+    }
+
+    pub fn server_url(&self) -> String {
+        format!("http://{}/signalr", self.address)
+    }
+
+    /// Settings for a user of this server, who only connects over websockets unless the test
+    /// picks another transport
+    pub fn settings(&self, username: &str) -> Settings {
+        Settings {
+            username: username.to_owned(),
+            full_name: format!("{} Example", username),
+            email: format!("{}@example.com", username),
+            server_url: self.server_url(),
+            transport: Some(TransportKind::WebSockets),
+            ..Settings::default()
+        }
+    }
+
+    fn hub(&self) -> std::sync::MutexGuard<'_, Hub> {
+        self.hub.lock().expect("Mock hub lock poisoned")
+    }
+
+    pub fn set_protocol_version(&self, version: &str) {
+        self.hub().protocol_version = version.to_owned();
+    }
+
+    /// Seconds the client may go without hearing from the server. It pings three times as often.
+    pub fn set_keep_alive_timeout(&self, seconds: f64) {
+        self.hub().keep_alive_timeout = seconds;
+    }
+
+    /// Adds a queue without telling anyone, as if it existed before the client connected
+    pub fn add_queue(&self, name: &str, status: QueueStatus) -> u64 {
+        let mut hub = self.hub();
+        let id = hub.next_queue_id;
+        hub.next_queue_id += 1;
+        hub.queues.insert(
+            id,
+            MockQueue {
+                id,
+                name: name.to_owned(),
+                status,
+                members: Vec::new(),
+                messages: Vec::new(),
+                restrict_to_group: String::new(),
+            },
+        );
+        id
+    }
+
+    /// Invokes a hub method as another user would, notifying every connection of the outcome
+    pub fn invoke_as(&self, username: &str, method: &str, args: Value) -> Value {
+        let user = MockUser::from_header(username);
+        let args = args.as_array().cloned().unwrap_or_default();
+        self.hub()
+            .invoke(&user, method, &args)
+            .expect("Mock invocation failed")
+    }
+
+    /// A queue as the server would send it, if it exists
+    pub fn queue(&self, id: u64) -> Option<Value> {
+        self.hub().queues.get(&id).map(MockQueue::to_wire)
+    }
+
+    /// Websocket pings received so far
+    pub fn pings(&self) -> usize {
+        self.hub().pings
+    }
+
+    /// Turns away every connection over `transport`
+    pub fn refuse_transport(&self, transport: TransportKind) {
+        self.hub().refused.insert(transport);
+    }
+
+    /// Transports connections have been opened or resumed with, in order
+    pub fn connected_transports(&self) -> Vec<TransportKind> {
+        self.hub().connected.clone()
+    }
+
+    pub fn set_long_poll_delay(&self, delay: Duration) {
+        self.hub().long_poll_delay = delay.as_millis() as u64;
+    }
+
+    /// When each long poll arrived, starting with the one that connected
+    pub fn long_polls(&self) -> Vec<Instant> {
+        self.hub().polls.clone()
+    }
+
+    /// Connection tokens the client has aborted
+    pub fn aborted(&self) -> Vec<String> {
+        self.hub().aborted.clone()
+    }
+
+    /// Pushes a notification to every client, however wrong it may be
+    pub fn notify(&self, method: &str, args: Value) {
+        self.hub().notify(method, args);
+    }
+
+    pub fn drop_connections(&self) {
+        let _ = self.hub().events.send(Event::DropConnections);
+    }
+}
+
+async fn handle(hub: Arc<Mutex<Hub>>, mut request: Request<Body>) -> Response<Body> {
+    let mut params: BTreeMap<String, String> = request
+        .uri()
+        .query()
+        .map(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    // Long polls send their cursor, and the HTTP transports their messages, as a form
+    if request.method() == Method::POST {
+        let form = hyper::body::to_bytes(request.body_mut())
+            .await
+            .unwrap_or_default();
+        params.extend(url::form_urlencoded::parse(&form).into_owned());
+    }
+    let user = MockUser::from_header(
+        request
+            .headers()
+            .get("User")
+            .and_then(|header| header.to_str().ok())
+            .unwrap_or_default(),
+    );
+
+    let Some(endpoint) = request
+        .uri()
+        .path()
+        .strip_prefix("/signalr/")
+        .map(str::to_owned)
+    else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    let method = request.method().clone();
+    let transport: Option<TransportKind> = params
+        .get("transport")
+        .and_then(|name| serde_json::from_value(json!(name)).ok());
+
+    let mut hub_guard = hub.lock().expect("Mock hub lock poisoned");
+    let hub_state = &mut *hub_guard;
+
+    if params.get("clientProtocol").map(String::as_str) != Some(PROTOCOL_VERSION) {
+        return status(StatusCode::BAD_REQUEST);
+    }
+    if endpoint == "negotiate" {
+        let connection_token = format!("token-{}", hub_state.connection_tokens.len() + 1);
+        hub_state.connection_tokens.insert(connection_token.clone());
+        return json_response(json!({
+            "Url": "/signalr",
+            "ConnectionToken": connection_token,
+            "ConnectionId": connection_token,
+            "KeepAliveTimeout": hub_state.keep_alive_timeout,
+            "DisconnectTimeout": 30.0,
+            "ConnectionTimeout": 110.0,
+            "TryWebSockets": true,
+            "ProtocolVersion": hub_state.protocol_version,
+            "TransportConnectTimeout": 5.0,
+            "LongPollDelay": 0.0,
+        }));
+    }
+
+    let connection_token = params.get("connectionToken").cloned().unwrap_or_default();
+    if !hub_state.connection_tokens.contains(&connection_token) {
+        return status(StatusCode::FORBIDDEN);
+    }
+
+    match (method, endpoint.as_str(), transport) {
+        (Method::GET, "start", _) => json_response(json!({ "Response": "started" })),
+        (Method::GET, "ping", _) => {
+            hub_state.pings += 1;
+            json_response(json!({ "Response": "pong" }))
+        }
+        (Method::POST, "abort", _) => {
+            hub_state.connection_tokens.remove(&connection_token);
+            hub_state.aborted.push(connection_token.clone());
+            let _ = hub_state.events.send(Event::Aborted { connection_token });
+            status(StatusCode::OK)
+        }
+        (_, "connect" | "reconnect", Some(transport)) if hub_state.refused.contains(&transport) => {
+            status(StatusCode::BAD_REQUEST)
+        }
+        (
+            Method::GET,
+            "connect" | "reconnect",
+            Some(transport @ (TransportKind::WebSockets | TransportKind::ServerSentEvents)),
+        ) => {
+            let key = request.headers().get(SEC_WEBSOCKET_KEY).cloned();
+            if transport == TransportKind::WebSockets && key.is_none() {
+                return status(StatusCode::BAD_REQUEST);
+            }
+            hub_state.connected.push(transport);
+
+            // Subscribe while holding the lock, so nothing slips between the backlog and the feed
+            let first_frame = first_frame(hub_state, &endpoint, &params);
+            let events = hub_state.events.subscribe();
+            drop(hub_guard);
+
+            let Some(key) = key else {
+                let (body, response) = Body::channel();
+                tokio::spawn(serve_events(body, connection_token, first_frame, events));
+
+                return Response::builder()
+                    .header("Content-Type", "text/event-stream")
+                    .body(response)
+                    .expect("Couldn't build event stream response");
+            };
+
+            tokio::spawn(async move {
+                let upgraded = hyper::upgrade::on(request)
+                    .await
+                    .expect("Couldn't upgrade mock connection");
+                let websocket =
+                    WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                serve_websocket(hub, websocket, user, connection_token, first_frame, events).await;
+            });
+
+            Response::builder()
+                .status(StatusCode::SWITCHING_PROTOCOLS)
+                .header(CONNECTION, "Upgrade")
+                .header(UPGRADE, "websocket")
+                .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(key.as_bytes()))
+                .body(Body::empty())
+                .expect("Couldn't build upgrade response")
+        }
+        (Method::POST, "connect" | "reconnect", Some(TransportKind::LongPolling)) => {
+            hub_state.connected.push(TransportKind::LongPolling);
+            hub_state.polls.push(Instant::now());
+            let mut frame = first_frame(hub_state, &endpoint, &params);
+            frame["L"] = json!(hub_state.long_poll_delay);
+            json_response(frame)
+        }
+        (Method::POST, "poll", Some(TransportKind::LongPolling)) => {
+            hub_state.polls.push(Instant::now());
+            let delay = hub_state.long_poll_delay;
+            let mut frame = first_frame(hub_state, "reconnect", &params);
+            frame["L"] = json!(delay);
+            if frame["M"] != json!([]) {
+                return json_response(frame);
+            }
+
+            // Nothing new, so hold the poll until there is
+            let mut events = hub_state.events.subscribe();
+            drop(hub_guard);
+            let (mut body, response) = Body::channel();
+            tokio::spawn(async move {
+                let mut frame =
+                    match timeout(POLL_TIMEOUT, next_frame(&mut events, &connection_token)).await {
+                        Ok(Some(next)) => next,
+                        Ok(None) => return body.abort(),
+                        Err(_) => frame,
+                    };
+                frame["L"] = json!(delay);
+                let _ = body.send_data(Bytes::from(frame.to_string())).await;
+            });
+
+            Response::builder()
+                .header("Content-Type", "application/json")
+                .body(response)
+                .expect("Couldn't build poll response")
+        }
+        (
+            Method::POST,
+            "send",
+            Some(TransportKind::ServerSentEvents | TransportKind::LongPolling),
+        ) => {
+            let data = params.get("data").cloned().unwrap_or_default();
+            json_response(invocation_reply(hub_state, &user, &data))
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+/// The frame a connection starts with: everything it missed if it's resuming, and the signal
+/// that it's initialized if it's new
+fn first_frame(hub: &Hub, endpoint: &str, params: &BTreeMap<String, String>) -> Value {
+    let resume_from = match endpoint {
+        "reconnect" => params
+            .get("messageId")
+            .and_then(|cursor| cursor.parse::<usize>().ok())
+            .unwrap_or_default(),
+        _ => hub.log.len(),
+    };
+    let backlog = hub.log.get(resume_from..).unwrap_or_default().to_vec();
+
+    json!({
+        "C": hub.log.len().to_string(),
+        "S": (endpoint == "connect").then_some(1),
+        "M": backlog,
+    })
+}
+
+/// Waits for the next frame to push to a connection, or `None` once it should be closed
+async fn next_frame(
+    events: &mut broadcast::Receiver<Event>,
+    connection_token: &str,
+) -> Option<Value> {
+    loop {
+        match events.recv().await {
+            Ok(Event::Notification { cursor, message }) => {
+                return Some(json!({ "C": cursor.to_string(), "M": [message] }))
+            }
+            Ok(Event::Aborted {
+                connection_token: aborted,
+            }) if aborted == connection_token => return None,
+            Ok(Event::Aborted { .. }) => continue,
+            Ok(Event::DropConnections) | Err(_) => return None,
+        }
+    }
+}
+
+async fn serve_websocket<S>(
+    hub: Arc<Mutex<Hub>>,
+    mut websocket: WebSocketStream<S>,
+    user: MockUser,
+    connection_token: String,
+    first_frame: Value,
+    mut events: broadcast::Receiver<Event>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    if websocket
+        .send(WebsocketMessage::text(first_frame.to_string()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        let outgoing = select! {
+            incoming = websocket.next() => match incoming {
+                Some(Ok(WebsocketMessage::Text(text))) => {
+                    invocation_reply(&mut hub.lock().expect("Mock hub lock poisoned"), &user, &text)
+                }
+                Some(Ok(WebsocketMessage::Ping(_))) => {
+                    // tungstenite answers with a pong by itself
+                    hub.lock().expect("Mock hub lock poisoned").pings += 1;
+                    continue;
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+            frame = next_frame(&mut events, &connection_token) => match frame {
+                Some(frame) => frame,
+                None => {
+                    let _ = websocket.close(None).await;
+                    return;
+                }
+            },
+        };
+
+        if websocket
+            .send(WebsocketMessage::text(outgoing.to_string()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Streams frames as server-sent events. Lines end in `\r\n` and every event is split across two
+/// chunks, as a real server and the proxies in front of it are free to do.
+async fn serve_events(
+    mut body: BodySender,
+    connection_token: String,
+    first_frame: Value,
+    mut events: broadcast::Receiver<Event>,
+) {
+    let mut data = vec!["initialized".to_owned(), first_frame.to_string()];
+
+    loop {
+        for data in data.drain(..) {
+            let event = format!("data: {}\r\n\r\n", data).into_bytes();
+            let (start, end) = event.split_at(event.len() / 2);
+            for chunk in [start, end] {
+                if body.send_data(Bytes::copy_from_slice(chunk)).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        match next_frame(&mut events, &connection_token).await {
+            Some(frame) => data.push(frame.to_string()),
+            None => return body.abort(),
+        }
+    }
+}
+
+/// Runs an invocation sent by the client and builds the reply, echoing its id as a string like
+/// the real server does
+fn invocation_reply(hub: &mut Hub, user: &MockUser, text: &str) -> Value {
+    let invocation: Value = serde_json::from_str(text).expect("Client sent invalid JSON");
+    let id = invocation["I"].to_string();
+    assert_eq!(invocation["H"], HUB_NAME, "Client invoked an unknown hub");
+
+    let method = invocation["M"].as_str().unwrap_or_default();
+    let args = invocation["A"].as_array().cloned().unwrap_or_default();
+    let result = hub.invoke(user, method, &args);
+
+    match result {
+        Ok(result) => json!({ "I": id, "R": result }),
+        Err(message) => json!({ "I": id, "E": message, "H": true }),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("Couldn't build response")
+}
+
+fn json_response(body: Value) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("Couldn't build response")
+}
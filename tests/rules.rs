@@ -0,0 +1,186 @@
+//! Picking how to alert the user to queue events from their notification rules.
+
+use rq::{
+    ClientEvent, EventKind, Message, NotificationAction, NotificationRule, Queue, QueueChange,
+    Settings,
+};
+
+mod common;
+
+use common::{queue, user};
+
+fn created(queue: Queue) -> ClientEvent {
+    ClientEvent::QueueChanged {
+        change: QueueChange::Created,
+        queue,
+    }
+}
+
+fn nagged(queue: Queue, by: &str) -> ClientEvent {
+    ClientEvent::Nagged {
+        queue,
+        by: Some(user(by)),
+    }
+}
+
+fn message(queue: Queue, from: &str, content: &str) -> ClientEvent {
+    ClientEvent::MessageSent {
+        queue,
+        message: Message {
+            content: content.to_owned(),
+            sender: user(from),
+        },
+    }
+}
+
+fn settings(rules: Vec<NotificationRule>) -> Settings {
+    Settings {
+        username: "alice".to_owned(),
+        notification_rules: rules,
+        ..Settings::default()
+    }
+}
+
+fn rule(actions: &[NotificationAction]) -> NotificationRule {
+    NotificationRule {
+        actions: actions.to_vec(),
+        ..NotificationRule::default()
+    }
+}
+
+#[test]
+fn without_rules_the_toggles_decide() {
+    let mut settings = settings(Vec::new());
+    settings.notifications.nagged = false;
+
+    assert_eq!(
+        settings.notification_actions(&created(queue("Lunch", "", &["bob"]))),
+        vec![NotificationAction::Notification]
+    );
+    assert!(settings
+        .notification_actions(&nagged(queue("Lunch", "", &["bob"]), "bob"))
+        .is_empty());
+    assert!(settings
+        .notification_actions(&ClientEvent::QueueChanged {
+            change: QueueChange::MembershipChanged,
+            queue: queue("Lunch", "", &["bob"]),
+        })
+        .is_empty());
+}
+
+#[test]
+fn the_first_matching_rule_wins() {
+    let settings = settings(vec![
+        NotificationRule {
+            queue_name: Some("*review*".to_owned()),
+            ..rule(&[])
+        },
+        rule(&[NotificationAction::Popup, NotificationAction::Sound]),
+    ]);
+
+    assert!(settings
+        .notification_actions(&created(queue("Code Review: rq", "", &["bob"])))
+        .is_empty());
+    assert_eq!(
+        settings.notification_actions(&created(queue("Lunch", "", &["bob"]))),
+        vec![NotificationAction::Popup, NotificationAction::Sound]
+    );
+}
+
+#[test]
+fn rules_match_on_every_condition() {
+    let settings = settings(vec![NotificationRule {
+        queue_name: Some("lunch*".to_owned()),
+        group: Some("developers".to_owned()),
+        member_only: true,
+        event: Some(EventKind::Nagged),
+        sender: Some("bob".to_owned()),
+        ..rule(&[NotificationAction::Popup])
+    }]);
+    let matching = queue("Lunch at 12", "developers", &["alice", "bob"]);
+
+    assert_eq!(
+        settings.notification_actions(&nagged(matching.clone(), "bob")),
+        vec![NotificationAction::Popup]
+    );
+
+    // Anything that doesn't match falls through to the toggles
+    for event in [
+        nagged(queue("Dinner", "developers", &["alice"]), "bob"),
+        nagged(queue("Lunch", "testers", &["alice"]), "bob"),
+        nagged(queue("Lunch", "developers", &["bob"]), "bob"),
+        nagged(matching.clone(), "carol"),
+        created(matching),
+    ] {
+        assert_eq!(
+            settings.notification_actions(&event),
+            vec![NotificationAction::Notification],
+            "{:?}",
+            event
+        );
+    }
+}
+
+#[test]
+fn senders_are_the_creators_of_new_queues() {
+    let settings = settings(vec![NotificationRule {
+        sender: Some("bob".to_owned()),
+        ..rule(&[])
+    }]);
+
+    assert!(settings
+        .notification_actions(&created(queue("Lunch", "", &["bob", "carol"])))
+        .is_empty());
+    assert_eq!(
+        settings.notification_actions(&created(queue("Lunch", "", &["carol", "bob"]))),
+        vec![NotificationAction::Notification]
+    );
+}
+
+#[test]
+fn messages_notify_members_and_anyone_mentioned() {
+    let mut settings = settings(Vec::new());
+    let notify = vec![NotificationAction::Notification];
+
+    assert_eq!(
+        settings.notification_actions(&message(queue("Lunch", "", &["alice"]), "bob", "Pizza?")),
+        notify
+    );
+    assert_eq!(
+        settings.notification_actions(&message(
+            queue("Lunch", "", &["bob"]),
+            "bob",
+            "@Alice pizza?"
+        )),
+        notify
+    );
+    assert!(settings
+        .notification_actions(&message(queue("Lunch", "", &["bob"]), "bob", "Pizza?"))
+        .is_empty());
+    assert!(settings
+        .notification_actions(&message(queue("Lunch", "", &["alice"]), "alice", "@alice"))
+        .is_empty());
+
+    settings.notifications.messages = false;
+    assert!(settings
+        .notification_actions(&message(queue("Lunch", "", &["alice"]), "bob", "Pizza?"))
+        .is_empty());
+}
+
+#[test]
+fn rules_can_match_mentions() {
+    let settings = settings(vec![NotificationRule {
+        event: Some(EventKind::Message),
+        mentioned: true,
+        ..rule(&[NotificationAction::Sound])
+    }]);
+
+    assert_eq!(
+        settings.notification_actions(&message(queue("Lunch", "", &[]), "bob", "@alice ready?")),
+        vec![NotificationAction::Sound]
+    );
+    assert_eq!(
+        settings.notification_actions(&message(queue("Lunch", "", &["alice"]), "bob", "Ready?")),
+        vec![NotificationAction::Notification]
+    );
+}